@@ -22,10 +22,11 @@
 //! Dynamically typed value.
 
 use capability::FromClientHook;
+use orphan::Orphan;
 use private::capability::{ClientHook, PipelineHook, PipelineOp};
 use private::layout::{PointerReader, PointerBuilder};
 use traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder};
-use Result;
+use {MessageSize, Result};
 
 #[derive(Copy, Clone)]
 pub struct Owned(());
@@ -55,11 +56,34 @@ impl <'a> Reader<'a> {
         self.reader.is_null()
     }
 
+    /// Gets the total size of the target and all of its children. Does not count far pointer
+    /// overhead.
+    pub fn total_size(&self) -> Result<MessageSize> {
+        self.reader.total_size()
+    }
+
     #[inline]
     pub fn get_as<T : FromPointerReader<'a>>(&self) -> Result<T> {
         FromPointerReader::get_from_pointer(&self.reader)
     }
 
+    /// Gets the bytes of a text object without checking that they are valid UTF-8. Useful when
+    /// a peer has put Latin-1 or other non-UTF-8 data in a text field.
+    pub fn get_text_bytes(&self) -> Result<&'a [u8]> {
+        self.reader.get_text_bytes(::std::ptr::null(), 0)
+    }
+
+    /// Gets a text object, replacing any invalid UTF-8 sequences with U+FFFD.
+    pub fn get_text_lossy(&self) -> Result<::std::borrow::Cow<'a, str>> {
+        Ok(::text::new_reader_lossy(try!(self.get_text_bytes())))
+    }
+
+    /// Compares the targets of two pointers by value, regardless of how either is laid out in
+    /// its message. Returns an error if either contains a capability.
+    pub fn equals(&self, other: &Reader) -> Result<bool> {
+        self.reader.equals(&other.reader)
+    }
+
     pub fn get_as_capability<T : FromClientHook>(&self) -> Result<T> {
         Ok(FromClientHook::new(try!(self.reader.get_capability())))
     }
@@ -90,7 +114,7 @@ impl <'a> FromPointerReader<'a> for Reader<'a> {
 
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
     fn set_pointer_builder<'b>(pointer: ::private::layout::PointerBuilder<'b>, value: Reader<'a>) -> Result<()> {
-        pointer.copy_from(value.reader)
+        pointer.copy_from(value.reader, false)
     }
 }
 
@@ -104,6 +128,11 @@ impl <'a> Builder<'a> {
         Builder { builder : builder }
     }
 
+    #[inline]
+    pub fn is_null(&self) -> bool {
+        self.builder.is_null()
+    }
+
     pub fn get_as<T : FromPointerBuilder<'a>>(self) -> Result<T> {
         FromPointerBuilder::get_from_pointer(self.builder)
     }
@@ -120,6 +149,26 @@ impl <'a> Builder<'a> {
         SetPointerBuilder::<To>::set_pointer_builder(self.builder, value)
     }
 
+    /// Sets this pointer to a text object holding the result of `format_args!`, without
+    /// formatting into an intermediate `String` first.
+    pub fn set_text_fmt(self, args: ::std::fmt::Arguments) -> ::std::fmt::Result {
+        self.builder.set_text_fmt(args)
+    }
+
+    /// Sets this pointer to a text object holding `wide` converted from UTF-16.
+    #[cfg(feature="utf16")]
+    pub fn set_text_from_utf16(self, wide: &[u16]) -> Result<()> {
+        let len = try!(::text::utf16_len_in_utf8(wide));
+        self.builder.init_text(len).push_utf16(wide)
+    }
+
+    /// Reallocates the text object that this pointer points to so that it has room for `size`
+    /// bytes, keeping as much of its contents as fits. Text builders cannot grow in place, so
+    /// this is the way to lengthen a text field.
+    pub fn resize_text(self, size: u32) -> Result<::text::Builder<'a>> {
+        self.builder.resize_text(size)
+    }
+
     // XXX value should be a user client.
     pub fn set_as_capability(&self, value : Box<ClientHook+Send>) {
         self.builder.set_capability(value);
@@ -130,6 +179,34 @@ impl <'a> Builder<'a> {
         self.builder.clear()
     }
 
+    /// Shortens the list that this pointer points to, in place. The elements that are cut off are
+    /// zeroed, but their space stays allocated.
+    pub fn truncate_list(&mut self, new_len: u32) -> Result<()> {
+        self.builder.truncate_list(new_len)
+    }
+
+    /// Changes the length of the list that this pointer points to, truncating it in place or
+    /// reallocating it with the existing elements copied over.
+    pub fn resize_list(&mut self, new_len: u32) -> Result<()> {
+        self.builder.resize_list(new_len)
+    }
+
+    /// Creates a new, null orphan in the same message as this pointer.
+    pub fn init_orphan(&self) -> Orphan<'a> {
+        Orphan::new(self.builder.init_orphan())
+    }
+
+    /// Detaches the target of this pointer, leaving the pointer null.
+    pub fn disown(&mut self) -> Orphan<'a> {
+        Orphan::new(self.builder.disown())
+    }
+
+    /// Makes this pointer point at the orphan's object. The orphan must belong to the same
+    /// message. Any object that this pointer previously pointed to is zeroed.
+    pub fn adopt(&mut self, orphan: Orphan<'a>) {
+        self.builder.adopt(orphan.into_internal())
+    }
+
     #[inline]
     pub fn as_reader(self) -> Reader<'a> {
         Reader { reader : self.builder.as_reader() }
@@ -173,3 +250,83 @@ impl Pipeline {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+    use message::{Builder, Reader, ReaderOptions, SegmentArray};
+    use primitive_list;
+    use text_list;
+    use Word;
+
+    #[test]
+    fn resize_text() {
+        let mut message = Builder::new_default();
+        message.set_root("h\u{e9}llo").unwrap();
+        {
+            let root: super::Builder = message.get_root().unwrap();
+            let mut text = root.resize_text(12).unwrap();
+            text.push_str(" there");
+        }
+        assert_eq!("h\u{e9}llo there", message.get_root_as_reader::<&str>().unwrap());
+        {
+            let root: super::Builder = message.get_root().unwrap();
+            let text = root.resize_text(2).unwrap();
+            assert_eq!(1, text.len());
+            assert_eq!(1, text.capacity());
+        }
+        assert_eq!("h", message.get_root_as_reader::<&str>().unwrap());
+    }
+
+    #[test]
+    fn set_text_fmt() {
+        let message = build_message(|root| {
+            root.set_text_fmt(format_args!("{}-{:03}", "id", 7)).unwrap();
+        });
+        assert_eq!("id-007", message.get_root_as_reader::<&str>().unwrap());
+    }
+
+    #[test]
+    fn resize_list() {
+        let mut message = build_message(|root| {
+            let mut list: text_list::Builder = root.initn_as(4);
+            for (idx, &text) in ["a", "b", "c", "d"].iter().enumerate() {
+                list.set(idx as u32, text);
+            }
+        });
+        message.get_root::<super::Builder>().unwrap().truncate_list(2).unwrap();
+        {
+            let list: text_list::Reader = message.get_root_as_reader().unwrap();
+            assert_eq!(vec!["a", "b"], list.collect_into_vec().unwrap());
+        }
+        message.get_root::<super::Builder>().unwrap().resize_list(3).unwrap();
+        {
+            let list: text_list::Reader = message.get_root_as_reader().unwrap();
+            assert_eq!(vec!["a", "b", ""], list.collect_into_vec().unwrap());
+        }
+        assert!(message.get_root::<super::Builder>().unwrap().truncate_list(4).is_err());
+
+        let mut message = build_message(|root| {
+            let mut list: primitive_list::Builder<u16> = root.initn_as(3);
+            list.set_from_slice(&[7, 8, 9]);
+        });
+        message.get_root::<super::Builder>().unwrap().resize_list(5).unwrap();
+        let list: primitive_list::Reader<u16> = message.get_root_as_reader().unwrap();
+        assert_eq!(vec![7, 8, 9, 0, 0], list.iter().collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn non_utf8_text() {
+        let mut message = Builder::new_default();
+        message.set_root("abc").unwrap();
+        let mut words = message.get_segments_for_output()[0].to_vec();
+        Word::words_to_bytes_mut(&mut words)[9] = 0xff;
+
+        let segments = [&words[..]];
+        let reader = Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert!(reader.get_root::<&str>().is_err());
+        let root: super::Reader = reader.get_root().unwrap();
+        assert_eq!(&[b'a', 0xff, b'c'], root.get_text_bytes().unwrap());
+        assert_eq!("a\u{fffd}c", root.get_text_lossy().unwrap());
+    }
+}
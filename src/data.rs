@@ -35,6 +35,8 @@ impl<'a> ::traits::Owned<'a> for Owned {
 pub type Reader<'a> = &'a [u8];
 
 pub fn new_reader<'a>(p : *const u8, len : u32) -> Reader<'a> {
+    // `p` may be null for an empty default value.
+    if len == 0 { return &[]; }
     unsafe { ::std::slice::from_raw_parts(p, len as usize) }
 }
 
@@ -44,12 +46,62 @@ impl <'a> ::traits::FromPointerReader<'a> for Reader<'a> {
     }
 }
 
+/// A type for which every bit pattern is a valid value, so that the bytes of a data field can be
+/// viewed as a slice of it.
+pub unsafe trait Plain : Copy {}
+
+macro_rules! plain_impl(
+    ($typ:ty) => (
+        unsafe impl Plain for $typ {}
+        );
+    );
+
+plain_impl!(u8);
+plain_impl!(i8);
+plain_impl!(u16);
+plain_impl!(i16);
+plain_impl!(u32);
+plain_impl!(i32);
+plain_impl!(u64);
+plain_impl!(i64);
+plain_impl!(f32);
+plain_impl!(f64);
+
+/// Views a data field as a slice of `T` without copying. Returns `None` if the bytes are not
+/// aligned for `T` or if their length is not a multiple of the size of `T`. Data fields start on
+/// a word boundary within their segment, so alignment only fails when the segment itself is
+/// misaligned.
+///
+/// The values are read in host byte order, so this is only available on little-endian targets.
+#[cfg(target_endian = "little")]
+pub fn as_aligned_slice<'a, T : Plain>(data : Reader<'a>) -> Option<&'a [T]> {
+    let size = ::std::mem::size_of::<T>();
+    if data.len() == 0 {
+        return Some(&[]);
+    }
+    if data.as_ptr() as usize % ::std::mem::align_of::<T>() != 0 || data.len() % size != 0 {
+        return None;
+    }
+    Some(unsafe { ::std::slice::from_raw_parts(data.as_ptr() as *const T, data.len() / size) })
+}
+
 pub type Builder<'a> = &'a mut [u8];
 
 pub fn new_builder<'a>(p : *mut u8, len : u32) -> Builder<'a> {
+    if len == 0 { return &mut []; }
     unsafe { ::std::slice::from_raw_parts_mut(p, len as usize) }
 }
 
+/// Overwrites every byte of a data field with zero, using volatile writes that the compiler cannot
+/// optimize away. Use this to scrub secrets such as keys out of a message before its buffers are
+/// reused or freed.
+pub fn zeroize(data : &mut [u8]) {
+    for byte in data.iter_mut() {
+        unsafe { ::std::ptr::write_volatile(byte, 0); }
+    }
+    ::std::sync::atomic::compiler_fence(::std::sync::atomic::Ordering::SeqCst);
+}
+
 impl <'a> ::traits::FromPointerBuilder<'a> for Builder<'a> {
     fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a> {
         builder.init_data(size)
@@ -66,3 +118,34 @@ impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use message::Builder;
+
+    #[test]
+    fn as_aligned_slice() {
+        let mut message = Builder::new_default();
+        let mut bytes = Vec::new();
+        for &x in [1.5f64, -2.0, 1e10].iter() {
+            bytes.extend_from_slice(&x.to_bits().to_le_bytes());
+        }
+        message.set_root(&bytes[..]).unwrap();
+        let root: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(Some(&[1.5f64, -2.0, 1e10][..]), super::as_aligned_slice::<f64>(root));
+        assert_eq!(6, super::as_aligned_slice::<u32>(root).unwrap().len());
+        assert_eq!(None, super::as_aligned_slice::<u64>(&root[1..9]));
+        assert_eq!(None, super::as_aligned_slice::<u32>(&root[..6]));
+    }
+
+    #[test]
+    fn zeroize() {
+        let mut message = Builder::new_default();
+        message.set_root(&b"secret"[..]).unwrap();
+        {
+            let secret: super::Builder = message.get_root().unwrap();
+            super::zeroize(secret);
+        }
+        assert_eq!(&[0u8; 6], message.get_root_as_reader::<super::Reader>().unwrap());
+    }
+}
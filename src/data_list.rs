@@ -21,7 +21,7 @@
 
 //! List of sequences of bytes.
 
-use traits::{FromPointerReader, FromPointerBuilder};
+use traits::{FromPointerReader, FromPointerBuilder, IndexMove, ListIter};
 use private::layout::*;
 use Result;
 
@@ -44,6 +44,10 @@ impl <'a> Reader<'a> {
     }
 
     pub fn len(&self) -> u32 { self.reader.len() }
+
+    pub fn iter(self) -> ListIter<Reader<'a>, Result<::data::Reader<'a>>> {
+        ListIter::new(self, self.len())
+    }
 }
 
 impl <'a> FromPointerReader<'a> for Reader<'a> {
@@ -59,6 +63,20 @@ impl <'a> Reader<'a> {
     }
 }
 
+impl <'a> IndexMove<u32, Result<::data::Reader<'a>>> for Reader<'a> {
+    fn index_move(&self, index : u32) -> Result<::data::Reader<'a>> {
+        self.get(index)
+    }
+}
+
+impl <'a> ::std::iter::IntoIterator for Reader<'a> {
+    type Item = Result<::data::Reader<'a>>;
+    type IntoIter = ListIter<Reader<'a>, Result<::data::Reader<'a>>>;
+    fn into_iter(self) -> ListIter<Reader<'a>, Result<::data::Reader<'a>>> {
+        self.iter()
+    }
+}
+
 pub struct Builder<'a> {
     builder : ListBuilder<'a>
 }
@@ -75,6 +93,16 @@ impl <'a> Builder<'a> {
         self.builder.get_pointer_element(index).set_data(value);
     }
 
+    /// Sets the first `values.len()` elements, allocating each one at the size of its payload.
+    ///
+    /// Panics if `values` is longer than the list.
+    pub fn set_all(&mut self, values : &[::data::Reader]) {
+        assert!(values.len() <= self.len() as usize);
+        for (idx, value) in values.iter().enumerate() {
+            self.set(idx as u32, value);
+        }
+    }
+
     pub fn borrow<'b>(&'b mut self) -> Builder<'b> {
         Builder {builder : self.builder}
     }
@@ -95,6 +123,11 @@ impl <'a> FromPointerBuilder<'a> for Builder<'a> {
 }
 
 impl <'a> Builder<'a> {
+    pub fn iter(self) -> ListIter<Builder<'a>, Result<::data::Builder<'a>>> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
+
     pub fn get(self, index : u32) -> Result<::data::Builder<'a>> {
         assert!(index < self.len());
         self.builder.get_pointer_element(index).get_data(::std::ptr::null(), 0)
@@ -102,6 +135,12 @@ impl <'a> Builder<'a> {
 }
 
 
+impl <'a> IndexMove<u32, Result<::data::Builder<'a>>> for Builder<'a> {
+    fn index_move(&self, index : u32) -> Result<::data::Builder<'a>> {
+        Builder { builder : self.builder }.get(index)
+    }
+}
+
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
     fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
                                value : Reader<'a>) -> Result<()> {
@@ -110,3 +149,37 @@ impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
     }
 }
 
+/// Allows a whole data list to be set from a slice of byte slices, sized to fit.
+impl <'a, 'c> ::traits::SetPointerBuilder<Builder<'a>> for &'c [::data::Reader<'c>] {
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : &'c [::data::Reader<'c>]) -> Result<()> {
+        let mut builder = Builder { builder : pointer.init_list(Pointer, value.len() as u32) };
+        builder.set_all(value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+
+    #[test]
+    fn from_slice() {
+        let message = build_message(|root| {
+            root.set_as::<super::Builder, _>(&[&[1u8, 2, 3][..], &[], &[4]][..]).unwrap();
+        });
+        let list: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(3, list.len());
+        assert_eq!(&[1, 2, 3], list.get(0).unwrap());
+        assert_eq!(0, list.get(1).unwrap().len());
+        assert_eq!(&[4], list.get(2).unwrap());
+
+        let message = build_message(|root| {
+            let mut list: super::Builder = root.initn_as(3);
+            list.set_all(&[&[9u8; 20][..]]);
+        });
+        let list: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(&[9; 20][..], list.get(0).unwrap());
+        assert!(list.get(2).unwrap().is_empty());
+    }
+}
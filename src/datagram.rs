@@ -0,0 +1,95 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Reading and writing of messages where each datagram carries exactly one message in the
+//! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream).
+
+use std::io;
+
+use message;
+use serialize;
+use {Error, Result};
+
+/// Reads a message from the contents of a single datagram.
+///
+/// The datagram must contain exactly one message; trailing bytes are reported as an error.
+/// The message is copied into owned space, since datagram buffers are not generally aligned.
+pub fn read_message_from_datagram(datagram: &[u8],
+                                  options: message::ReaderOptions)
+                                  -> Result<message::Reader<serialize::OwnedSegments>> {
+    let mut remaining = datagram;
+    let message = try!(serialize::read_message(&mut remaining, options));
+    if remaining.len() != 0 {
+        return Err(Error::new_decode_error("Datagram contains trailing bytes after message.",
+                                           Some(format!("{}", remaining.len()))));
+    }
+    Ok(message)
+}
+
+/// Serializes `message` into a buffer suitable for sending as a single datagram.
+///
+/// Returns an error of kind `InvalidInput` if the serialized message is larger than `mtu` bytes.
+pub fn write_message_to_datagram<A>(message: &message::Builder<A>, mtu: usize) -> io::Result<Vec<u8>>
+    where A: message::Allocator
+{
+    let byte_count = serialize::compute_serialized_size_in_words(message) * 8;
+    if byte_count > mtu {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("Message of {} bytes does not fit in a datagram of {} bytes.",
+                                          byte_count, mtu)));
+    }
+    let mut result = Vec::with_capacity(byte_count);
+    try!(serialize::write_message(&mut result, message));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use message;
+    use super::{read_message_from_datagram, write_message_to_datagram};
+
+    #[test]
+    fn round_trip() {
+        let mut message = message::Builder::new_default();
+        message.set_root("datagram").unwrap();
+
+        let bytes = write_message_to_datagram(&message, 1500).unwrap();
+        let reader = read_message_from_datagram(&bytes[..], message::ReaderOptions::new()).unwrap();
+        assert_eq!("datagram", reader.get_root::<&str>().unwrap());
+    }
+
+    #[test]
+    fn exceeds_mtu() {
+        let mut message = message::Builder::new_default();
+        message.set_root("datagram").unwrap();
+        assert!(write_message_to_datagram(&message, 16).is_err());
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut message = message::Builder::new_default();
+        message.set_root("datagram").unwrap();
+
+        let mut bytes = write_message_to_datagram(&message, 1500).unwrap();
+        bytes.extend([0u8; 8].iter().cloned());
+        assert!(read_message_from_datagram(&bytes[..], message::ReaderOptions::new()).is_err());
+    }
+}
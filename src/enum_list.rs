@@ -21,7 +21,7 @@
 
 //! List of enums.
 
-use traits::{FromPointerReader, FromPointerBuilder, ToU16, FromU16};
+use traits::{FromPointerReader, FromPointerBuilder, ToU16, FromU16, IndexMove, ListIter};
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder,
                       TwoBytes, PrimitiveElement};
 use {NotInSchema, Result};
@@ -49,6 +49,10 @@ impl <'a, T: FromU16> Reader<'a, T> {
 
     pub fn len(&self) -> u32 { self.reader.len() }
 
+    pub fn iter(self) -> ListIter<Reader<'a, T>, ::std::result::Result<T, NotInSchema>> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
 }
 
 impl <'a, T : FromU16> FromPointerReader<'a> for Reader<'a, T> {
@@ -64,6 +68,27 @@ impl <'a, T : FromU16> Reader<'a, T> {
         let result : u16 = PrimitiveElement::get(&self.reader, index);
         FromU16::from_u16(result)
     }
+
+    /// Gets the numeric value of an element, even if it is an enumerant that is not in the
+    /// schema, e.g. one added by a newer version of the schema.
+    pub fn get_raw(&self, index : u32) -> u16 {
+        assert!(index < self.len());
+        PrimitiveElement::get(&self.reader, index)
+    }
+}
+
+impl <'a, T : FromU16> IndexMove<u32, ::std::result::Result<T, NotInSchema>> for Reader<'a, T> {
+    fn index_move(&self, index : u32) -> ::std::result::Result<T, NotInSchema> {
+        self.get(index)
+    }
+}
+
+impl <'a, T : FromU16> ::std::iter::IntoIterator for Reader<'a, T> {
+    type Item = ::std::result::Result<T, NotInSchema>;
+    type IntoIter = ListIter<Reader<'a, T>, ::std::result::Result<T, NotInSchema>>;
+    fn into_iter(self) -> ListIter<Reader<'a, T>, ::std::result::Result<T, NotInSchema>> {
+        self.iter()
+    }
 }
 
 pub struct Builder<'a, T> {
@@ -82,6 +107,13 @@ impl <'a, T : ToU16 + FromU16> Builder<'a, T> {
         assert!(index < self.len());
         PrimitiveElement::set(&self.builder, index, value.to_u16());
     }
+
+    /// Sets an element to a numeric value, which need not be an enumerant in the schema. Useful
+    /// for forwarding values that were read with `Reader::get_raw()`.
+    pub fn set_raw(&mut self, index : u32, value : u16) {
+        assert!(index < self.len());
+        PrimitiveElement::set(&self.builder, index, value);
+    }
 }
 
 impl <'a, T : FromU16> FromPointerBuilder<'a> for Builder<'a, T> {
@@ -101,6 +133,11 @@ impl <'a, T : ToU16 + FromU16>  Builder<'a, T> {
         let result : u16 = PrimitiveElement::get_from_builder(&self.builder, index);
         FromU16::from_u16(result)
     }
+
+    pub fn get_raw(&self, index : u32) -> u16 {
+        assert!(index < self.len());
+        PrimitiveElement::get_from_builder(&self.builder, index)
+    }
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
@@ -111,3 +148,41 @@ impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T> {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+    use traits::{FromU16, ToU16};
+    use NotInSchema;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Color { Red, Green }
+
+    impl FromU16 for Color {
+        fn from_u16(value: u16) -> ::std::result::Result<Color, NotInSchema> {
+            match value {
+                0 => Ok(Color::Red),
+                1 => Ok(Color::Green),
+                n => Err(NotInSchema(n)),
+            }
+        }
+    }
+
+    impl ToU16 for Color {
+        fn to_u16(self) -> u16 { self as u16 }
+    }
+
+    #[test]
+    fn raw() {
+        let message = build_message(|root| {
+            let mut list: super::Builder<Color> = root.initn_as(2);
+            list.set(0, Color::Green);
+            list.set_raw(1, 7);
+            assert_eq!(7, list.get_raw(1));
+        });
+        let list: super::Reader<Color> = message.get_root_as_reader().unwrap();
+        assert_eq!(1, list.get_raw(0));
+        assert_eq!(7, list.get_raw(1));
+        assert_eq!(vec![Ok(Color::Green), Err(NotInSchema(7))], list.iter().collect::<Vec<_>>());
+    }
+}
@@ -35,22 +35,39 @@
 //! distributed object-capability layer.
 
 #![allow(raw_pointer_derive)]
+#![cfg_attr(feature = "unstable", feature(test))]
 
 extern crate byteorder;
 
 #[cfg(any(feature="quickcheck", test))]
 extern crate quickcheck;
 
+#[cfg(all(test, feature="unstable"))]
+extern crate test;
+
+#[cfg(feature="mmap")]
+extern crate memmap;
+
+#[cfg(feature="lz4")]
+extern crate lz4;
+
+#[cfg(feature="zstd")]
+extern crate zstd;
+
 pub mod any_pointer;
 pub mod capability;
 pub mod data;
 pub mod data_list;
+pub mod datagram;
 pub mod enum_list;
 pub mod list_list;
 pub mod message;
+pub mod orphan;
 pub mod primitive_list;
 pub mod private;
 pub mod serialize;
+#[cfg(any(feature="lz4", feature="zstd"))]
+pub mod serialize_compressed;
 pub mod serialize_packed;
 pub mod struct_list;
 pub mod text;
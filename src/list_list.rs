@@ -66,6 +66,14 @@ where T: for<'b> ::traits::Owned<'b> {
     }
 }
 
+impl <'a, T> ::std::iter::IntoIterator for Reader<'a, T> where T: for<'b> ::traits::Owned<'b> {
+    type Item = Result<<T as ::traits::Owned<'a>>::Reader>;
+    type IntoIter = ListIter<Reader<'a, T>, Result<<T as ::traits::Owned<'a>>::Reader>>;
+    fn into_iter(self) -> ListIter<Reader<'a, T>, Result<<T as ::traits::Owned<'a>>::Reader>> {
+        self.iter()
+    }
+}
+
 impl <'a, T> FromPointerReader<'a> for Reader<'a, T> where T: for<'b> ::traits::Owned<'b> {
     fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
         Ok(Reader { reader : try!(reader.get_list(Pointer, ::std::ptr::null())),
@@ -103,6 +111,18 @@ impl <'a, T> Builder<'a, T> where T: for<'b> ::traits::Owned<'b> {
     pub fn borrow<'b>(&'b mut self) -> Builder<'b, T> {
         Builder {builder : self.builder, marker : ::std::marker::PhantomData}
     }
+
+    /// Initializes the inner list at `index` to `size` elements, borrowing this builder rather
+    /// than consuming it, so that several elements can be built in turn.
+    pub fn init_element<'b>(&'b mut self, index: u32, size: u32) -> <T as ::traits::Owned<'b>>::Builder {
+        assert!(index < self.len());
+        self.borrow().init(index, size)
+    }
+
+    /// Gets the inner list at `index`, borrowing this builder rather than consuming it.
+    pub fn get_element<'b>(&'b mut self, index: u32) -> Result<<T as ::traits::Owned<'b>>::Builder> {
+        self.borrow().get(index)
+    }
 }
 
 impl <'a, T> FromPointerBuilder<'a> for Builder<'a, T> where T: for<'b> ::traits::Owned<'b> {
@@ -121,12 +141,24 @@ impl <'a, T> FromPointerBuilder<'a> for Builder<'a, T> where T: for<'b> ::traits
 }
 
 impl <'a, T> Builder<'a, T> where T: for<'b> ::traits::Owned<'b> {
+    pub fn iter(self) -> ListIter<Builder<'a, T>, Result<<T as ::traits::Owned<'a>>::Builder>> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
+
     pub fn get(self, index : u32) -> Result<<T as ::traits::Owned<'a>>::Builder> {
         assert!(index < self.len());
         FromPointerBuilder::get_from_pointer(self.builder.get_pointer_element(index))
     }
 }
 
+impl <'a, T>  IndexMove<u32, Result<<T as ::traits::Owned<'a>>::Builder>> for Builder<'a, T>
+where T: for<'b> ::traits::Owned<'b> {
+    fn index_move(&self, index : u32) -> Result<<T as ::traits::Owned<'a>>::Builder> {
+        Builder::<'a, T> { builder : self.builder, marker : ::std::marker::PhantomData }.get(index)
+    }
+}
+
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
     where T: for<'b> ::traits::Owned<'b>
 {
@@ -136,3 +168,24 @@ impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+    use primitive_list;
+
+    #[test]
+    fn init_element() {
+        let message = build_message(|root| {
+            let mut outer: super::Builder<primitive_list::Owned<u16>> = root.initn_as(2);
+            for idx in 0..2 {
+                let mut inner = outer.init_element(idx, idx + 1);
+                inner.set(idx, 7);
+            }
+            outer.get_element(0).unwrap().set(0, 5);
+        });
+        let outer: super::Reader<primitive_list::Owned<u16>> = message.get_root_as_reader().unwrap();
+        assert_eq!(vec![5], outer.get(0).unwrap().iter().collect::<Vec<u16>>());
+        assert_eq!(vec![0, 7], outer.get(1).unwrap().iter().collect::<Vec<u16>>());
+    }
+}
@@ -26,8 +26,8 @@ use private::capability::ClientHook;
 use private::units::*;
 use private::arena::{BuilderArena, ReaderArena, SegmentBuilder, SegmentReader};
 use private::layout;
-use traits::{FromPointerReader, FromPointerBuilder, SetPointerBuilder};
-use {OutputSegments, Result, Word};
+use traits::{FromPointerReader, FromPointerBuilder, Owned, SetPointerBuilder};
+use {MessageSize, OutputSegments, Result, Word};
 
 /// Options controlling how data is read.
 #[derive(Clone, Copy)]
@@ -59,10 +59,15 @@ pub struct ReaderOptions {
     /// being very large. The default limit of 64 is probably low enough to prevent any chance of
     /// stack overflow, yet high enough that it is never a problem in practice.
     pub nesting_limit : i32,
+
+    /// Determines what happens when a text field that is not valid UTF-8 is read. The default is
+    /// `Utf8Policy::Strict`.
+    pub utf8_policy : Utf8Policy,
 }
 
 pub const DEFAULT_READER_OPTIONS : ReaderOptions =
-    ReaderOptions { traversal_limit_in_words : 8 * 1024 * 1024, nesting_limit : 64 };
+    ReaderOptions { traversal_limit_in_words : 8 * 1024 * 1024, nesting_limit : 64,
+                    utf8_policy : Utf8Policy::Strict };
 
 impl ReaderOptions {
     pub fn new() -> ReaderOptions { DEFAULT_READER_OPTIONS }
@@ -76,6 +81,23 @@ impl ReaderOptions {
         self.traversal_limit_in_words = value;
         return self;
     }
+
+    pub fn utf8_policy<'a>(&'a mut self, value : Utf8Policy) -> &'a mut ReaderOptions {
+        self.utf8_policy = value;
+        return self;
+    }
+}
+
+/// How text fields are checked for valid UTF-8 when they are read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Text that is not valid UTF-8 is an error. This is the default.
+    Strict,
+
+    /// Text is cut off before its first invalid UTF-8 sequence, so a malformed field reads as
+    /// its valid prefix instead of failing. Unlike `any_pointer::Reader::get_text_lossy()`,
+    /// nothing after the first invalid sequence is kept.
+    ValidPrefix,
 }
 
 type SegmentId = u32;
@@ -137,20 +159,164 @@ impl <S> Reader<S> where S: ReaderSegments {
         }
     }
 
+    /// Checks whether the message is in
+    /// [canonical form](https://capnproto.org/encoding.html#canonicalization): a single segment
+    /// with no far pointers, objects laid out in preorder, and struct sections truncated so that
+    /// they do not end in zero words or null pointers.
+    pub fn is_canonical(&self) -> Result<bool> {
+        if self.segments.get_segment(1).is_some() {
+            return Ok(false);
+        }
+        unsafe {
+            let segment : *const SegmentReader = &self.arena.segment0;
+            let start = (*segment).get_start_ptr();
+            let pointer_reader = try!(layout::PointerReader::get_root(
+                segment, start, self.options.nesting_limit));
+
+            let mut read_head = start.offset(WORDS_PER_POINTER as isize);
+            let root_is_canonical = try!(pointer_reader.is_canonical(&mut read_head));
+            let all_words_consumed = read_head == start.offset((*segment).size as isize);
+            Ok(root_is_canonical && all_words_consumed)
+        }
+    }
+
+    /// Gets the total size of the message's root object and all of its children. Does not count
+    /// the root pointer or far pointer overhead.
+    pub fn total_size(&self) -> Result<MessageSize> {
+        try!(self.get_root_internal()).total_size()
+    }
+
     /// Gets the root of the message, interpreting it as the given type.
     pub fn get_root<'a, T : FromPointerReader<'a>>(&'a self) -> Result<T> {
         try!(self.get_root_internal()).get_as()
     }
 
+    /// Attaches the capabilities that the message's capability pointers refer to by index.
+    /// Must be called before any capability is read from the message.
     pub fn init_cap_table(&mut self, cap_table : Vec<Option<Box<ClientHook+Send>>>) {
         self.arena.init_cap_table(cap_table);
     }
 
+    pub fn get_cap_table<'a>(&'a self) -> &'a [Option<Box<ClientHook+Send>>] {
+        &self.arena.cap_table
+    }
+
     pub fn into_segments(self) -> S {
         *self.segments
     }
 }
 
+/// A message reader whose root is known to be of type `T`. It owns its segments, so unlike a
+/// reader obtained from `Reader::get_root()` it can be stored in a struct, queued, or sent to
+/// another thread, and the typed root can be fetched again wherever it ends up.
+pub struct TypedReader<S, T> where S: ReaderSegments, T: for<'a> Owned<'a> {
+    marker: ::std::marker::PhantomData<T>,
+    message: Reader<S>,
+}
+
+impl <S, T> TypedReader<S, T> where S: ReaderSegments, T: for<'a> Owned<'a> {
+    pub fn new(message: Reader<S>) -> TypedReader<S, T> {
+        TypedReader { marker: ::std::marker::PhantomData, message: message }
+    }
+
+    pub fn get<'a>(&'a self) -> Result<<T as Owned<'a>>::Reader> {
+        self.message.get_root()
+    }
+
+    pub fn into_inner(self) -> Reader<S> {
+        self.message
+    }
+}
+
+impl <S, T> From<Reader<S>> for TypedReader<S, T> where S: ReaderSegments, T: for<'a> Owned<'a> {
+    fn from(message: Reader<S>) -> TypedReader<S, T> {
+        TypedReader::new(message)
+    }
+}
+
+/// A message builder whose root is of type `T`, so that the root accessors need no type
+/// annotations and cannot be used with the wrong type by accident.
+pub struct TypedBuilder<T, A = HeapAllocator> where T: for<'a> Owned<'a>, A: Allocator {
+    marker: ::std::marker::PhantomData<T>,
+    message: Builder<A>,
+}
+
+impl <T> TypedBuilder<T, HeapAllocator> where T: for<'a> Owned<'a> {
+    pub fn new_default() -> TypedBuilder<T, HeapAllocator> {
+        TypedBuilder::new(Builder::new_default())
+    }
+}
+
+impl <T, A> TypedBuilder<T, A> where T: for<'a> Owned<'a>, A: Allocator {
+    pub fn new(message: Builder<A>) -> TypedBuilder<T, A> {
+        TypedBuilder { marker: ::std::marker::PhantomData, message: message }
+    }
+
+    pub fn init_root<'a>(&'a mut self) -> <T as Owned<'a>>::Builder {
+        self.message.init_root()
+    }
+
+    pub fn get_root<'a>(&'a mut self) -> Result<<T as Owned<'a>>::Builder> {
+        self.message.get_root()
+    }
+
+    pub fn get_root_as_reader<'a>(&'a self) -> Result<<T as Owned<'a>>::Reader> {
+        self.message.get_root_as_reader()
+    }
+
+    pub fn set_root<'a>(&mut self, value: <T as Owned<'a>>::Reader) -> Result<()> {
+        self.message.set_root(value)
+    }
+
+    pub fn borrow_inner(&self) -> &Builder<A> {
+        &self.message
+    }
+
+    pub fn into_inner(self) -> Builder<A> {
+        self.message
+    }
+}
+
+/// Deep-copies the root of `reader` into a new builder whose first segment is large enough to
+/// hold the entire message. The result therefore has a single segment and contains no far
+/// pointers.
+pub fn flatten<S>(reader: &Reader<S>) -> Result<Builder<HeapAllocator>> where S: ReaderSegments {
+    let root = try!(reader.get_root_internal());
+    let size = try!(root.total_size());
+
+    // One extra word for the root pointer.
+    let mut message = Builder::new(
+        HeapAllocator::new().first_segment_words(size.word_count as u32 + WORDS_PER_POINTER as u32));
+    try!(message.set_root(root));
+    Ok(message)
+}
+
+/// Copies the message into
+/// [canonical form](https://capnproto.org/encoding.html#canonicalization) and returns the
+/// resulting single segment, root pointer included. Two messages with the same content produce
+/// identical words, which makes the result suitable for hashing or signing.
+pub fn canonicalize<S>(reader: &Reader<S>) -> Result<Vec<Word>> where S: ReaderSegments {
+    let size = try!(reader.total_size());
+
+    // Canonical form never takes more space than the original, plus one word for the root pointer.
+    let mut message = Builder::new(
+        HeapAllocator::new().first_segment_words(size.word_count as u32 + WORDS_PER_POINTER as u32));
+    unsafe {
+        let segment : *const SegmentReader = &reader.arena.segment0;
+        let root = try!(layout::PointerReader::get_root(
+            segment, (*segment).get_start_ptr(), reader.options.nesting_limit));
+        try!(message.get_root_pointer().copy_from(root, true));
+    }
+
+    let segments = message.get_segments_for_output();
+    if segments.len() != 1 {
+        return Err(::Error::new_decode_error(
+            "Canonical copy of message did not fit in a single segment.",
+            Some(format!("estimated size: {} words", size.word_count))));
+    }
+    Ok(segments[0].to_vec())
+}
+
 /// An object that allocates memory for a Cap'n Proto message as it is being built.
 pub unsafe trait Allocator {
     /// Allocates memory for a new segment, returning a pointer to the start of the segment
@@ -160,6 +326,14 @@ pub unsafe trait Allocator {
     /// for the lifetime of the object and doesn't overlap with other allocated memory.
     fn allocate_segment(&mut self, miniumum_size: u32) -> (*mut Word, u32);
 
+    /// Like `allocate_segment`, but returns `None` if the allocator cannot provide a segment of
+    /// at least `minimum_size` words. The message builder then carries on in heap memory of its
+    /// own and reports the failure through `Builder::check_allocation()`, rather than panicking.
+    /// The default implementation defers to `allocate_segment`.
+    fn try_allocate_segment(&mut self, minimum_size: u32) -> Option<(*mut Word, u32)> {
+        Some(self.allocate_segment(minimum_size))
+    }
+
     fn pre_drop(&mut self, _segment0_currently_allocated: u32) {}
 }
 
@@ -186,7 +360,7 @@ impl <A> Builder<A> where A: Allocator {
         Builder { arena: arena, allocator: boxed_allocator }
     }
 
-    fn get_root_internal<'a>(&mut self) -> any_pointer::Builder<'a> {
+    fn get_root_pointer<'a>(&mut self) -> layout::PointerBuilder<'a> {
         let root_segment: *mut SegmentBuilder = &mut self.arena.segment0;
 
         if self.arena.segment0.current_size() == 0 {
@@ -196,40 +370,179 @@ impl <A> Builder<A> where A: Allocator {
                     assert!(location == self.arena.segment0.get_ptr_unchecked(0),
                             "First allocated word of new segment was not at offset 0");
 
-                    any_pointer::Builder::new(layout::PointerBuilder::get_root(root_segment, location))
+                    layout::PointerBuilder::get_root(root_segment, location)
 
                 }
             }
         } else {
-            any_pointer::Builder::new(
-                layout::PointerBuilder::get_root(root_segment,
-                                                 self.arena.segment0.get_ptr_unchecked(0)))
+            layout::PointerBuilder::get_root(root_segment,
+                                             self.arena.segment0.get_ptr_unchecked(0))
         }
 
     }
 
+    fn get_root_internal<'a>(&mut self) -> any_pointer::Builder<'a> {
+        any_pointer::Builder::new(self.get_root_pointer())
+    }
+
     /// Initializes the root as a value of the given type.
     pub fn init_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> T {
         self.get_root_internal().init_as()
     }
 
+    /// Like `init_root`, but returns an error if the allocator could not provide space for the
+    /// root. Space for what is built under the root afterwards is checked by
+    /// `check_allocation()`.
+    pub fn try_init_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> Result<T> {
+        let root = self.get_root_internal().init_as();
+        try!(self.check_allocation());
+        Ok(root)
+    }
+
     /// Gets the root, interpreting it as the given type.
     pub fn get_root<'a, T : FromPointerBuilder<'a>>(&'a mut self) -> Result<T> {
         self.get_root_internal().get_as()
     }
 
-    /// Sets the root to a deep copy of the given value.
+    /// Gets a read-only view of the root, interpreting it as the given type. Unlike serializing
+    /// the message and reading it back, this does not copy anything.
+    pub fn get_root_as_reader<'a, T : FromPointerReader<'a>>(&'a self) -> Result<T> {
+        if self.arena.segment0.current_size() == 0 {
+            any_pointer::Reader::new(layout::PointerReader::new_default()).get_as()
+        } else {
+            let segment: *const SegmentReader = &self.arena.segment0.reader;
+            let root = try!(layout::PointerReader::get_root(
+                segment, unsafe { (*segment).get_start_ptr() }, ::std::i32::MAX));
+            any_pointer::Reader::new(root).get_as()
+        }
+    }
+
+    /// Sets the root to a deep copy of the given value. Returns an error if the allocator could
+    /// not provide space for it.
     pub fn set_root<To, From : SetPointerBuilder<To>>(&mut self, value : From) -> Result<()> {
-        self.get_root_internal().set_as(value)
+        try!(self.get_root_internal().set_as(value));
+        self.check_allocation()
+    }
+
+    /// Returns an error if the allocator could not provide space for everything built so far,
+    /// i.e. if `Allocator::try_allocate_segment` returned `None`. Building carries on in heap
+    /// memory when that happens, so nothing is written out of bounds, but the message no longer
+    /// lives where the allocator put it and should be discarded, e.g. with `reset()`.
+    pub fn check_allocation(&self) -> Result<()> {
+        if self.arena.allocation_failed() {
+            Err(::Error::new_decode_error("Message does not fit in the space provided by its allocator.",
+                                          None))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Clears the message so that the builder can be reused for another one. Segments that have
+    /// already been allocated are zeroed and kept, so a builder that is reset between messages of
+    /// similar size stops allocating after the first one.
+    pub fn reset(&mut self) {
+        self.arena.reset();
     }
 
+    /// Gets the segments of the message, e.g. for writing it out.
+    ///
+    /// Panics if the allocator could not provide space for the message, i.e. if
+    /// `check_allocation()` would return an error, so that a message built partly in heap
+    /// fallback space is never sent on as if it lived where the allocator put it.
     pub fn get_segments_for_output<'a>(&'a self) -> OutputSegments<'a> {
+        if self.arena.allocation_failed() {
+            panic!("Message does not fit in the space provided by its allocator.");
+        }
         self.arena.get_segments_for_output()
     }
 
     pub fn get_cap_table<'a>(&'a self) -> &'a [Option<Box<ClientHook+Send>>] {
         self.arena.get_cap_table()
     }
+
+    /// Records the current allocation state, so that it can later be returned to with
+    /// `restore()`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot { segment_sizes: self.arena.segment_sizes(), cap_count: self.arena.cap_table.len() }
+    }
+
+    /// Rolls the builder back to the state recorded in `snapshot`. Everything allocated since
+    /// then is zeroed and its space becomes available again, which makes it possible to build
+    /// something speculatively, e.g. as an orphan, and back out if it turns out to be too large.
+    ///
+    /// Only allocation is rolled back. Any pointer that was set since the snapshot in an object
+    /// that already existed at the time must be cleared before restoring, or it will be left
+    /// pointing at reclaimed space.
+    ///
+    /// Panics if the builder has fewer segments than when the snapshot was taken, e.g. because
+    /// it has been reset since.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.arena.rewind(&snapshot.segment_sizes, snapshot.cap_count);
+    }
+
+    /// Converts the builder into a reader over the same segments, without serializing the
+    /// message. Capabilities set on the builder are carried over to the reader's cap table.
+    pub fn into_reader(mut self, options: ReaderOptions) -> Reader<Builder<A>> {
+        // Make sure that the root pointer exists.
+        self.get_root_internal();
+        let cap_table = ::std::mem::replace(&mut self.arena.cap_table, Vec::new());
+        let mut reader = Reader::new(self, options);
+        reader.init_cap_table(cap_table);
+        reader
+    }
+
+    /// Reports how much of each segment allocated so far is in use, in segment order.
+    pub fn segment_stats(&self) -> Vec<SegmentStats> {
+        let mut result = Vec::with_capacity(1 + self.arena.more_segments.len());
+        result.push(SegmentStats::new(&self.arena.segment0));
+        for segment in self.arena.more_segments.iter() {
+            result.push(SegmentStats::new(segment));
+        }
+        result
+    }
+
+    /// Gets the total number of words that have been allocated but not used, summed over all
+    /// segments.
+    pub fn wasted_words(&self) -> u64 {
+        self.segment_stats().iter().map(|stats| stats.unused_words() as u64).sum()
+    }
+}
+
+impl <A> ReaderSegments for Builder<A> where A: Allocator {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [Word]> {
+        if id == 0 {
+            Some(self.arena.segment0.currently_allocated())
+        } else {
+            self.arena.more_segments.get(id as usize - 1).map(|segment| segment.currently_allocated())
+        }
+    }
+}
+
+/// Allocation state of a message builder. See `Builder::snapshot()`.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    segment_sizes: Vec<u32>,
+    cap_count: usize,
+}
+
+/// Allocation statistics for one segment of a message builder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentStats {
+    /// Size of the segment as returned by the allocator.
+    pub allocated_words: u32,
+
+    /// Number of words at the start of the segment that hold message data.
+    pub used_words: u32,
+}
+
+impl SegmentStats {
+    fn new(segment: &SegmentBuilder) -> SegmentStats {
+        SegmentStats { allocated_words: segment.reader.size, used_words: segment.current_size() }
+    }
+
+    pub fn unused_words(&self) -> u32 {
+        self.allocated_words - self.used_words
+    }
 }
 
 impl <A> Drop for Builder<A> where A: Allocator {
@@ -345,3 +658,493 @@ unsafe impl <'a, 'b: 'a> Allocator for ScratchSpaceHeapAllocator<'a, 'b> {
         self.scratch_space.in_use = false;
     }
 }
+
+/// An allocator that builds a message entirely within a caller-provided buffer. A message that
+/// fits therefore has exactly one segment, and building it never allocates on the heap.
+///
+/// The buffer is zeroed when the builder claims it. If a message does not fit in the buffer,
+/// building carries on in space allocated on the heap, so that nothing is written out of
+/// bounds. Accessors such as `init_root()` do not report this; `Builder::set_root()` and
+/// `Builder::check_allocation()` return an error, and `Builder::get_segments_for_output()`
+/// panics. Call `check_allocation()` after building to be sure the message fits.
+pub struct SingleSegmentAllocator<'a> {
+    segment: &'a mut [Word],
+    in_use: bool,
+}
+
+impl <'a> SingleSegmentAllocator<'a> {
+    pub fn new(segment: &'a mut [Word]) -> SingleSegmentAllocator<'a> {
+        SingleSegmentAllocator { segment: segment, in_use: false }
+    }
+}
+
+unsafe impl <'a> Allocator for SingleSegmentAllocator<'a> {
+    fn allocate_segment(&mut self, minimum_size: u32) -> (*mut Word, u32) {
+        match self.try_allocate_segment(minimum_size) {
+            Some(result) => result,
+            None => panic!("message does not fit in the {}-word buffer of a SingleSegmentAllocator",
+                           self.segment.len()),
+        }
+    }
+
+    fn try_allocate_segment(&mut self, minimum_size: u32) -> Option<(*mut Word, u32)> {
+        if self.in_use || (minimum_size as usize) > self.segment.len() {
+            return None;
+        }
+        self.in_use = true;
+        unsafe {
+            ::std::ptr::write_bytes(self.segment.as_mut_ptr(), 0u8, self.segment.len());
+        }
+        Some((self.segment.as_mut_ptr(), self.segment.len() as u32))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{canonicalize, flatten, Builder, HeapAllocator, ReaderOptions, SegmentArray,
+                SegmentStats, SingleSegmentAllocator, TypedBuilder, TypedReader};
+    use private::capability::ClientHook;
+    use private::layout::StructSize;
+    use Word;
+
+    /// Builds a message on the heap, handing `init` its root to fill in.
+    pub fn build_message<F>(init: F) -> Builder<HeapAllocator>
+        where F: FnOnce(::any_pointer::Builder)
+    {
+        let mut message = Builder::new_default();
+        init(message.init_root());
+        message
+    }
+
+    #[test]
+    fn flatten_multi_segment() {
+        // A tiny first segment forces the text into a second segment behind a far pointer.
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        assert_eq!(2, message.get_segments_for_output().len());
+
+        let segments = message.get_segments_for_output();
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        let flat = flatten(&reader).unwrap();
+
+        assert_eq!(1, flat.get_segments_for_output().len());
+        let flat_segments = flat.get_segments_for_output();
+        let flat_reader = super::Reader::new(SegmentArray::new(&flat_segments), ReaderOptions::new());
+        assert_eq!("this text does not fit in the first segment",
+                   flat_reader.get_root::<&str>().unwrap());
+    }
+
+    #[test]
+    fn canonicalize_struct() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        {
+            let root = message.get_root_pointer().init_struct(StructSize { data: 2, pointers: 2 });
+            root.set_data_field::<u64>(0, 0x0102);
+            root.get_pointer_field(0).set_text("this text does not fit in the first segment");
+        }
+        let segments = message.get_segments_for_output();
+        assert_eq!(3, segments.len());
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        let words = canonicalize(&reader).unwrap();
+
+        // Root pointer, one data word, one pointer, and six words of text.
+        assert_eq!(9, words.len());
+        let canonical_segments = [&words[..]];
+        let canonical = super::Reader::new(SegmentArray::new(&canonical_segments), ReaderOptions::new());
+        assert!(canonical.is_canonical().unwrap());
+        assert_eq!(words, canonicalize(&canonical).unwrap());
+    }
+
+    #[test]
+    fn reset() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        assert_eq!(2, message.get_segments_for_output().len());
+        let second_segment = message.get_segments_for_output()[1].as_ptr();
+
+        message.reset();
+        assert_eq!(1, message.get_segments_for_output().len());
+        assert_eq!(0, message.get_segments_for_output()[0].len());
+        assert_eq!("", message.get_root_as_reader::<&str>().unwrap());
+
+        message.set_root("another text that does not fit either").unwrap();
+        let segments = message.get_segments_for_output();
+        assert_eq!(2, segments.len());
+        assert_eq!(second_segment, segments[1].as_ptr());
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert_eq!("another text that does not fit either", reader.get_root::<&str>().unwrap());
+    }
+
+    #[test]
+    fn typed_builder() {
+        let mut message: TypedBuilder<::text::Owned> = TypedBuilder::new_default();
+        message.set_root("typed").unwrap();
+        assert_eq!("typed", message.get_root_as_reader().unwrap());
+        assert_eq!(5, message.get_root().unwrap().len());
+        assert_eq!(1, message.borrow_inner().get_segments_for_output().len());
+    }
+
+    #[test]
+    fn typed_reader() {
+        let mut message = Builder::new_default();
+        message.set_root("typed").unwrap();
+        let words = message.get_segments_for_output()[0].to_vec();
+        let len = words.len();
+
+        let typed: TypedReader<_, ::text::Owned> =
+            super::Reader::new(::serialize::OwnedSegments::from_parts(words, vec![(0, len)]),
+                               ReaderOptions::new()).into();
+        let handle = ::std::thread::spawn(move || {
+            assert_eq!("typed", typed.get().unwrap());
+        });
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn equals() {
+        let mut wide = Builder::new(HeapAllocator::new().first_segment_words(1));
+        {
+            let root = wide.get_root_pointer().init_struct(StructSize { data: 2, pointers: 2 });
+            root.set_data_field::<u64>(0, 0x0102);
+            root.get_pointer_field(0).set_text("laid out differently");
+        }
+        let mut narrow = Builder::new_default();
+        let narrow_root = narrow.get_root_pointer().init_struct(StructSize { data: 1, pointers: 1 });
+        narrow_root.set_data_field::<u64>(0, 0x0102);
+        narrow_root.get_pointer_field(0).set_text("laid out differently");
+
+        {
+            let wide = wide.get_root_as_reader::<::any_pointer::Reader>().unwrap();
+            let narrow = narrow.get_root_as_reader::<::any_pointer::Reader>().unwrap();
+            assert!(wide.equals(&narrow).unwrap());
+            assert!(narrow.equals(&wide).unwrap());
+        }
+
+        narrow_root.set_data_field::<u64>(0, 0x0103);
+        let wide = wide.get_root_as_reader::<::any_pointer::Reader>().unwrap();
+        let narrow = narrow.get_root_as_reader::<::any_pointer::Reader>().unwrap();
+        assert!(!wide.equals(&narrow).unwrap());
+    }
+
+    #[test]
+    fn segment_stats() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(4));
+        assert_eq!(vec![SegmentStats { allocated_words: 4, used_words: 0 }], message.segment_stats());
+
+        // The root pointer fits in the first segment, but the text does not.
+        message.set_root("this text does not fit in the first segment").unwrap();
+        let stats = message.segment_stats();
+        assert_eq!(2, stats.len());
+        assert_eq!(SegmentStats { allocated_words: 4, used_words: 1 }, stats[0]);
+        assert_eq!(7, stats[1].used_words);
+        assert_eq!(3 + stats[1].unused_words() as u64, message.wasted_words());
+    }
+
+    #[test]
+    fn far_pointer_loop() {
+        // The root is a far pointer to a landing pad that is a far pointer to itself.
+        let segment0 = [Word::from(0x0000000100000002)];
+        let segment1 = [Word::from(0x0000000100000002)];
+        let segments = [&segment0[..], &segment1[..]];
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert!(reader.total_size().is_err());
+        assert!(flatten(&reader).is_err());
+    }
+
+    #[test]
+    fn nesting_limit_list_of_lists() {
+        use any_pointer;
+        use list_list;
+
+        let message = build_message(|mut pointer| {
+            for _ in 0..100 {
+                let list: list_list::Builder<any_pointer::Owned> = pointer.initn_as(1);
+                pointer = list.get(0).unwrap();
+            }
+        });
+
+        let mut options = ReaderOptions::new();
+        options.nesting_limit(10);
+        let segments = message.get_segments_for_output();
+        let reader = super::Reader::new(SegmentArray::new(&segments), options);
+        let mut pointer: any_pointer::Reader = reader.get_root().unwrap();
+        let mut depth = 0;
+        while let Ok(list) = pointer.get_as::<list_list::Reader<any_pointer::Owned>>() {
+            pointer = list.get(0).unwrap();
+            depth += 1;
+        }
+        assert_eq!(10, depth);
+    }
+
+    struct TestHook(u32);
+
+    impl ClientHook for TestHook {
+        fn copy(&self) -> Box<ClientHook+Send> { Box::new(TestHook(self.0)) }
+        fn new_call(&self, _interface_id: u64, _method_id: u16, _size_hint: Option<::MessageSize>)
+                    -> ::capability::Request<::any_pointer::Owned, ::any_pointer::Owned> {
+            panic!("not called in this test")
+        }
+        fn call(&self, _interface_id: u64, _method_id: u16,
+                _context: Box<::private::capability::CallContextHook+Send>) {
+            panic!("not called in this test")
+        }
+        fn get_descriptor(&self) -> Box<::std::any::Any> { Box::new(self.0) }
+    }
+
+    struct TestClient(Box<ClientHook+Send>);
+
+    impl ::capability::FromClientHook for TestClient {
+        fn new(hook: Box<ClientHook+Send>) -> TestClient { TestClient(hook) }
+    }
+
+    #[test]
+    fn cap_table() {
+        let mut message = Builder::new_default();
+        message.init_root::<::any_pointer::Builder>().set_as_capability(Box::new(TestHook(7)));
+        assert_eq!(1, message.get_cap_table().len());
+
+        let segments = message.get_segments_for_output();
+        let mut reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        let cap_table = message.get_cap_table().iter()
+            .map(|hook| hook.as_ref().map(|hook| hook.copy())).collect();
+        reader.init_cap_table(cap_table);
+        assert_eq!(1, reader.get_cap_table().len());
+
+        let client: TestClient =
+            reader.get_root::<::any_pointer::Reader>().unwrap().get_as_capability().unwrap();
+        assert_eq!(Some(&7), client.0.get_descriptor().downcast_ref::<u32>());
+
+        // Copying the message carries the capability over into the new message's table.
+        let flat = flatten(&reader).unwrap();
+        assert_eq!(1, flat.get_cap_table().len());
+
+        let mut empty = Builder::new_default();
+        empty.init_root::<::any_pointer::Builder>();
+        let empty_segments = empty.get_segments_for_output();
+        let empty_reader = super::Reader::new(SegmentArray::new(&empty_segments), ReaderOptions::new());
+        assert!(empty_reader.get_root::<::any_pointer::Reader>().unwrap()
+                .get_as_capability::<TestClient>().is_err());
+    }
+
+    #[test]
+    fn into_reader() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        let reader = message.into_reader(ReaderOptions::new());
+        assert_eq!("this text does not fit in the first segment", reader.get_root::<&str>().unwrap());
+
+        let empty = Builder::new_default().into_reader(ReaderOptions::new());
+        assert_eq!("", empty.get_root::<&str>().unwrap());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        use any_pointer;
+
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(4));
+        message.init_root::<any_pointer::Builder>();
+        let snapshot = message.snapshot();
+        {
+            let root: any_pointer::Builder = message.get_root().unwrap();
+            let mut orphan = root.init_orphan();
+            orphan.get().set_as("this text does not fit in the first segment").unwrap();
+            drop(orphan);
+        }
+        assert_eq!(2, message.get_segments_for_output().len());
+
+        message.restore(&snapshot);
+        let segments = message.get_segments_for_output();
+        assert_eq!(1, segments.len());
+        assert_eq!(1, segments[0].len());
+        assert_eq!(Word::from(0), segments[0][0]);
+
+        message.set_root("fits").unwrap();
+        assert_eq!("fits", message.get_root_as_reader::<&str>().unwrap());
+    }
+
+    #[test]
+    fn amplified_list_counts() {
+        use primitive_list;
+
+        // A one-word message whose root claims 2^29 - 1 void elements.
+        let huge_void = [Word::from(0xfffffff800000001)];
+        let segments = [&huge_void[..]];
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert!(reader.get_root::<primitive_list::Reader<()>>().is_err());
+
+        // The same claim for one-byte elements overruns the segment.
+        let huge_bytes = [Word::from(0xfffffffa00000001)];
+        let segments = [&huge_bytes[..]];
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert!(reader.get_root::<primitive_list::Reader<u8>>().is_err());
+
+        // Smaller claims are charged against the traversal limit.
+        let small_void = [Word::from(0x00001f4000000001)];
+        let segments = [&small_void[..]];
+        let mut options = ReaderOptions::new();
+        options.traversal_limit_in_words(2000);
+        let reader = super::Reader::new(SegmentArray::new(&segments), options);
+        assert_eq!(1000, reader.get_root::<primitive_list::Reader<()>>().unwrap().len());
+        assert!(reader.get_root::<primitive_list::Reader<()>>().is_err());
+    }
+
+    #[test]
+    fn single_segment_allocator() {
+        let mut buffer = vec![Word::from(0xffffffffffffffff); 8];
+        {
+            let mut message = Builder::new(SingleSegmentAllocator::new(&mut buffer[..]));
+            message.set_root("fits").unwrap();
+            let segments = message.get_segments_for_output();
+            assert_eq!(1, segments.len());
+            let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+            assert_eq!("fits", reader.get_root::<&str>().unwrap());
+        }
+        assert_eq!(Word::from(0), buffer[7]);
+    }
+
+    #[test]
+    fn single_segment_allocator_exhausted() {
+        let mut buffer = vec![Word::from(0); 2];
+        let mut message = Builder::new(SingleSegmentAllocator::new(&mut buffer[..]));
+        assert!(message.set_root("this text does not fit in the buffer").is_err());
+        assert!(message.check_allocation().is_err());
+
+        // Once the oversized message is discarded, the buffer can be used again.
+        message.reset();
+        assert!(message.check_allocation().is_ok());
+        message.set_root("ok").unwrap();
+        assert_eq!(1, message.get_segments_for_output().len());
+
+        // Not even the root pointer fits in a one-word buffer.
+        let mut buffer = vec![Word::from(0); 1];
+        let mut message = Builder::new(SingleSegmentAllocator::new(&mut buffer[..]));
+        assert!(message.try_init_root::<::any_pointer::Builder>().is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Message does not fit")]
+    fn single_segment_allocator_exhausted_output() {
+        let mut buffer = vec![Word::from(0); 2];
+        let mut message = Builder::new(SingleSegmentAllocator::new(&mut buffer[..]));
+        {
+            // Accessors carry on in heap space without reporting anything...
+            let root: ::any_pointer::Builder = message.init_root();
+            root.initn_as::<::text::Builder>(100);
+        }
+        // ...but the message cannot be written out.
+        message.get_segments_for_output();
+    }
+
+    #[test]
+    fn total_size() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        let segments = message.get_segments_for_output();
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        let size = reader.total_size().unwrap();
+        // 44 bytes of text including the NUL terminator.
+        assert_eq!(6, size.word_count);
+        assert_eq!(0, size.cap_count);
+    }
+
+    #[test]
+    fn get_root_as_reader() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        assert_eq!("", message.get_root_as_reader::<&str>().unwrap());
+        message.set_root("this text does not fit in the first segment").unwrap();
+        assert_eq!("this text does not fit in the first segment",
+                   message.get_root_as_reader::<&str>().unwrap());
+    }
+
+    #[test]
+    fn is_canonical() {
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        {
+            let segments = message.get_segments_for_output();
+            let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+            assert!(!reader.is_canonical().unwrap());
+        }
+
+        let flat = {
+            let segments = message.get_segments_for_output();
+            flatten(&super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new())).unwrap()
+        };
+        let segments = flat.get_segments_for_output();
+        let reader = super::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert!(reader.is_canonical().unwrap());
+
+        // Trailing garbage after the root object is not canonical.
+        let mut words = segments[0].to_vec();
+        words.push(::Word::from(0));
+        let padded = [&words[..]];
+        let reader = super::Reader::new(SegmentArray::new(&padded), ReaderOptions::new());
+        assert!(!reader.is_canonical().unwrap());
+    }
+
+    #[test]
+    fn copy_struct_content() {
+        let mut source = Builder::new_default();
+        {
+            let root = source.get_root_pointer().init_struct(StructSize { data: 2, pointers: 2 });
+            root.set_data_field::<u64>(0, 11);
+            root.set_data_field::<u64>(1, 22);
+            root.get_pointer_field(0).set_text("first");
+            root.get_pointer_field(1).set_text("second");
+        }
+        let source_root = source.get_root_pointer().as_reader().get_struct(::std::ptr::null()).unwrap();
+
+        let mut message = Builder::new_default();
+        {
+            let list = message.get_root_pointer()
+                .init_struct_list(2, StructSize { data: 1, pointers: 1 });
+            list.get_struct_element(1).get_pointer_field(0).set_text("old");
+            list.get_struct_element(1).copy_content_from(&source_root).unwrap();
+        }
+        let list = message.get_root_pointer().as_reader()
+            .get_list(::private::layout::InlineComposite, ::std::ptr::null()).unwrap();
+        let element = list.get_struct_element(1);
+        assert_eq!(11, element.get_data_field::<u64>(0));
+        assert_eq!("first", element.get_pointer_field(0).get_text(::std::ptr::null(), 0).unwrap());
+        assert_eq!(0, list.get_struct_element(0).get_data_field::<u64>(0));
+    }
+
+    #[test]
+    fn resize_struct_list() {
+        let mut message = Builder::new_default();
+        {
+            let list = message.get_root_pointer()
+                .init_struct_list(3, StructSize { data: 1, pointers: 1 });
+            for i in 0..3 {
+                list.get_struct_element(i).set_data_field::<u64>(0, i as u64 + 1);
+                list.get_struct_element(i).get_pointer_field(0).set_text("x");
+            }
+        }
+        message.get_root_pointer().truncate_list(1).unwrap();
+        message.get_root_pointer().resize_list(2).unwrap();
+        let list = message.get_root_pointer().as_reader()
+            .get_list(::private::layout::InlineComposite, ::std::ptr::null()).unwrap();
+        assert_eq!(2, list.len());
+        assert_eq!(1, list.get_struct_element(0).get_data_field::<u64>(0));
+        assert_eq!(0, list.get_struct_element(1).get_data_field::<u64>(0));
+        assert!(list.get_struct_element(1).get_pointer_field(0).is_null());
+    }
+
+    #[test]
+    fn utf8_policy() {
+        use super::Utf8Policy;
+
+        let mut message = Builder::new_default();
+        message.set_root("abc").unwrap();
+        let mut words = message.get_segments_for_output()[0].to_vec();
+        Word::words_to_bytes_mut(&mut words)[9] = 0xff;
+        let segments = [&words[..]];
+
+        let mut options = ReaderOptions::new();
+        options.utf8_policy(Utf8Policy::ValidPrefix);
+        let reader = super::Reader::new(SegmentArray::new(&segments), options);
+        assert_eq!("a", reader.get_root::<&str>().unwrap());
+        assert_eq!(Utf8Policy::ValidPrefix, options.utf8_policy);
+    }
+
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Objects that have been detached from, or not yet attached to, the tree of their message.
+//!
+//! Orphans make it possible to build a message bottom-up: an object can be created and filled
+//! in before it is known where it belongs, and then adopted by a pointer field. Likewise, an
+//! existing object can be disowned by one pointer and adopted by another without copying it.
+
+use any_pointer;
+use private::layout::OrphanBuilder;
+
+/// An object that belongs to a message but is not reachable from its root. If the orphan is
+/// dropped without being adopted, its object is zeroed.
+pub struct Orphan<'a> {
+    builder: OrphanBuilder<'a>,
+}
+
+impl <'a> Orphan<'a> {
+    #[doc(hidden)]
+    pub fn new(builder: OrphanBuilder<'a>) -> Orphan<'a> {
+        Orphan { builder: builder }
+    }
+
+    #[doc(hidden)]
+    pub fn into_internal(self) -> OrphanBuilder<'a> {
+        self.builder
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.builder.is_null()
+    }
+
+    /// Gets a builder for the orphaned object, through which it can be initialized or modified.
+    /// The builder borrows the orphan, so the orphan cannot be adopted while it is in use.
+    pub fn get<'b>(&'b mut self) -> any_pointer::Builder<'b> {
+        any_pointer::Builder::new(self.builder.as_pointer())
+    }
+
+    pub fn get_reader<'b>(&'b self) -> any_pointer::Reader<'b> {
+        any_pointer::Reader::new(self.builder.as_pointer().as_reader())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use any_pointer;
+    use message::{Builder, HeapAllocator, ReaderOptions, SegmentArray};
+
+    #[test]
+    fn adopt_orphan() {
+        let mut message = Builder::new_default();
+        {
+            let mut root: any_pointer::Builder = message.init_root();
+            let mut orphan = root.init_orphan();
+            assert!(orphan.is_null());
+            orphan.get().set_as("built before it had a home").unwrap();
+            assert!(root.is_null());
+            root.adopt(orphan);
+        }
+        let segments = message.get_segments_for_output();
+        let reader = ::message::Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        assert_eq!("built before it had a home", reader.get_root::<&str>().unwrap());
+    }
+
+    #[test]
+    fn disown_and_readopt() {
+        // A tiny first segment spreads the objects across segments, so that adoption needs far
+        // pointers.
+        let mut message = Builder::new(HeapAllocator::new().first_segment_words(1));
+        message.set_root("moved without copying").unwrap();
+        {
+            let mut root: any_pointer::Builder = message.get_root().unwrap();
+            let orphan = root.disown();
+            assert!(root.is_null());
+            assert_eq!("moved without copying", orphan.get_reader().get_as::<&str>().unwrap());
+            root.adopt(orphan);
+        }
+        assert_eq!("moved without copying", message.get_root_as_reader::<&str>().unwrap());
+    }
+
+    #[test]
+    fn drop_orphan() {
+        let mut message = Builder::new_default();
+        {
+            let root: any_pointer::Builder = message.init_root();
+            let mut orphan = root.init_orphan();
+            orphan.get().set_as("never adopted").unwrap();
+        }
+        let segments = message.get_segments_for_output();
+        assert!(segments[0].iter().all(|word| *word == ::Word::from(0)));
+    }
+}
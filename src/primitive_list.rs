@@ -21,9 +21,9 @@
 
 //! List of primitives.
 
-use traits::{FromPointerReader, FromPointerBuilder};
+use traits::{FromPointerReader, FromPointerBuilder, IndexMove, ListIter};
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder,
-                      PrimitiveElement, element_size_for_type};
+                      PrimitiveElement};
 use Result;
 
 #[derive(Clone, Copy)]
@@ -48,11 +48,16 @@ impl <'a, T : PrimitiveElement> Reader<'a, T> {
     }
 
     pub fn len(&self) -> u32 { self.reader.len() }
+
+    pub fn iter(self) -> ListIter<Reader<'a, T>, T> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
 }
 
 impl <'a, T : PrimitiveElement> FromPointerReader<'a> for Reader<'a, T> {
     fn get_from_pointer(reader : &PointerReader<'a>) -> Result<Reader<'a, T>> {
-        Ok(Reader { reader : try!(reader.get_list(element_size_for_type::<T>(), ::std::ptr::null())),
+        Ok(Reader { reader : try!(reader.get_list(T::element_size(), ::std::ptr::null())),
                     marker : ::std::marker::PhantomData })
     }
 }
@@ -64,6 +69,101 @@ impl <'a, T : PrimitiveElement> Reader<'a, T> {
     }
 }
 
+impl <'a, T : PrimitiveElement> Reader<'a, T> {
+    /// Gets the elements as a slice without copying. Returns `None` if they are not laid out
+    /// contiguously, e.g. if the list was written as a list of structs, or if they are not
+    /// aligned for `T` because the message was read from unaligned memory.
+    ///
+    /// The elements are read in host byte order, so this is only available on little-endian
+    /// targets.
+    #[cfg(target_endian = "little")]
+    pub fn as_slice(&self) -> Option<&'a [T]> where T : ::data::Plain {
+        let size = ::std::mem::size_of::<T>();
+        match self.reader.as_raw_bytes(size) {
+            Some(bytes) if bytes.len() == 0 => Some(&[]),
+            Some(bytes) if bytes.as_ptr() as usize % ::std::mem::align_of::<T>() == 0 => {
+                Some(unsafe {
+                    ::std::slice::from_raw_parts(bytes.as_ptr() as *const T, self.len() as usize)
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl <'a> Reader<'a, bool> {
+    /// Counts the elements that are `true`.
+    pub fn count_ones(&self) -> u32 {
+        self.words().map(|word| word.count_ones()).fold(0, |a, b| a + b)
+    }
+
+    /// Iterates over the elements 64 at a time. Element `64 * i + j` is bit `j` of the `i`th
+    /// word; bits past the end of the list are zero. When the list is bit-packed, each word is
+    /// read directly from the message rather than assembled one element at a time.
+    pub fn words(&self) -> Words<'a> {
+        Words { reader : self.reader, index : 0 }
+    }
+}
+
+/// Iterator over the elements of a list of bools, packed into `u64`s. See `Reader::words()`.
+pub struct Words<'a> {
+    reader : ListReader<'a>,
+    index : u32,
+}
+
+impl <'a> ::std::iter::Iterator for Words<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        let len = self.reader.len();
+        if self.index >= len {
+            return None;
+        }
+        let count = ::std::cmp::min(64, len - self.index);
+        let mut word = 0u64;
+        match self.reader.as_bit_bytes() {
+            Some(bytes) => {
+                let start = (self.index / 8) as usize;
+                let end = ((self.index + count + 7) / 8) as usize;
+                for (shift, &byte) in bytes[start..end].iter().enumerate() {
+                    word |= (byte as u64) << (shift * 8);
+                }
+                if count < 64 {
+                    word &= (1u64 << count) - 1;
+                }
+            }
+            None => {
+                for bit in 0..count {
+                    if <bool as PrimitiveElement>::get(&self.reader, self.index + bit) {
+                        word |= 1u64 << bit;
+                    }
+                }
+            }
+        }
+        self.index += count;
+        Some(word)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = ((self.reader.len() - self.index + 63) / 64) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl <'a, T : PrimitiveElement> IndexMove<u32, T> for Reader<'a, T> {
+    fn index_move(&self, index : u32) -> T {
+        self.get(index)
+    }
+}
+
+impl <'a, T : PrimitiveElement> ::std::iter::IntoIterator for Reader<'a, T> {
+    type Item = T;
+    type IntoIter = ListIter<Reader<'a, T>, T>;
+    fn into_iter(self) -> ListIter<Reader<'a, T>, T> {
+        self.iter()
+    }
+}
+
 pub struct Builder<'a, T> where T: PrimitiveElement {
     marker : ::std::marker::PhantomData<T>,
     builder : ListBuilder<'a>
@@ -79,15 +179,63 @@ impl <'a, T> Builder<'a, T> where T: PrimitiveElement {
     pub fn set(&mut self, index : u32, value : T) {
         PrimitiveElement::set(&self.builder, index, value);
     }
+
+    /// Copies `values` into the start of the list. When the elements are laid out contiguously
+    /// and the target is little-endian, this is a single memcpy.
+    ///
+    /// Panics if `values` is longer than the list.
+    pub fn set_from_slice(&mut self, values : &[T]) where T : Copy {
+        assert!(values.len() <= self.len() as usize);
+        let size = ::std::mem::size_of::<T>();
+        if cfg!(target_endian = "little") {
+            if let Some(bytes) = self.builder.as_raw_bytes_mut(size) {
+                unsafe {
+                    ::std::ptr::copy_nonoverlapping(values.as_ptr() as *const u8, bytes.as_mut_ptr(),
+                                                    values.len() * size);
+                }
+                return;
+            }
+        }
+        for (idx, &value) in values.iter().enumerate() {
+            self.set(idx as u32, value);
+        }
+    }
+}
+
+impl <'a> Builder<'a, bool> {
+    /// Copies `values` into the start of the list. When the list is bit-packed, the bits are
+    /// written a byte at a time.
+    ///
+    /// Panics if `values` is longer than the list.
+    pub fn set_from_bools(&mut self, values : &[bool]) {
+        assert!(values.len() <= self.len() as usize);
+        match self.builder.as_bit_bytes_mut() {
+            Some(bytes) => {
+                for (byte, chunk) in bytes.iter_mut().zip(values.chunks(8)) {
+                    let mut bits = 0u8;
+                    for (bit, &value) in chunk.iter().enumerate() {
+                        bits |= (value as u8) << bit;
+                    }
+                    let mask = if chunk.len() == 8 { 0xff } else { (1u8 << chunk.len()) - 1 };
+                    *byte = (*byte & !mask) | bits;
+                }
+            }
+            None => {
+                for (idx, &value) in values.iter().enumerate() {
+                    self.set(idx as u32, value);
+                }
+            }
+        }
+    }
 }
 
 impl <'a, T: PrimitiveElement> FromPointerBuilder<'a> for Builder<'a, T> {
     fn init_pointer(builder : PointerBuilder<'a>, size : u32) -> Builder<'a, T> {
-        Builder { builder : builder.init_list(element_size_for_type::<T>(), size),
+        Builder { builder : builder.init_list(T::element_size(), size),
                   marker : ::std::marker::PhantomData }
     }
     fn get_from_pointer(builder : PointerBuilder<'a>) -> Result<Builder<'a, T>> {
-        Ok(Builder { builder : try!(builder.get_list(element_size_for_type::<T>(), ::std::ptr::null())),
+        Ok(Builder { builder : try!(builder.get_list(T::element_size(), ::std::ptr::null())),
                      marker : ::std::marker::PhantomData })
     }
 }
@@ -108,3 +256,71 @@ impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+    use message::{Reader, ReaderOptions, SegmentArray};
+    use Word;
+
+    #[test]
+    fn set_from_slice() {
+        let message = build_message(|root| {
+            let mut list: super::Builder<f32> = root.initn_as(4);
+            list.set_from_slice(&[1.0, 2.5, -3.0]);
+        });
+        let list: super::Reader<f32> = message.get_root_as_reader().unwrap();
+        assert_eq!(vec![1.0, 2.5, -3.0, 0.0], list.iter().collect::<Vec<f32>>());
+        assert_eq!(Some(&[1.0, 2.5, -3.0, 0.0][..]), list.as_slice());
+
+        let message = build_message(|root| {
+            let mut list: super::Builder<bool> = root.initn_as(10);
+            list.set_from_slice(&[true, false, true]);
+        });
+        let list: super::Reader<bool> = message.get_root_as_reader().unwrap();
+        assert_eq!(vec![true, false, true, false], list.iter().take(4).collect::<Vec<bool>>());
+    }
+
+    #[test]
+    fn bool_bits() {
+        let values: Vec<bool> = (0..70).map(|i| i % 3 == 0).collect();
+        let message = build_message(|root| {
+            let mut list: super::Builder<bool> = root.initn_as(70);
+            list.set(69, true);
+            list.set_from_bools(&values[..68]);
+        });
+        // The elements are packed one bit apiece, after the root pointer.
+        assert_eq!(3, message.get_segments_for_output()[0].len());
+        let list: super::Reader<bool> = message.get_root_as_reader().unwrap();
+        assert_eq!(values[..68].to_vec(), list.iter().take(68).collect::<Vec<bool>>());
+        assert!(!list.get(68));
+        assert!(list.get(69));
+        assert_eq!(24, list.count_ones());
+
+        let words: Vec<u64> = list.words().collect();
+        assert_eq!(2, words.len());
+        assert_eq!(0x9249249249249249, words[0]);
+        assert_eq!(0x24, words[1]);
+    }
+
+    #[test]
+    fn bool_wire_format() {
+        // A root list pointer with element size 1 (bit) and 10 elements, followed by the bits
+        // of elements 0, 2, 3 and 9.
+        let fixture = [Word::from(0x0000005100000001), Word::from(0x020d)];
+
+        let segments = [&fixture[..]];
+        let reader = Reader::new(SegmentArray::new(&segments), ReaderOptions::new());
+        let list: super::Reader<bool> = reader.get_root().unwrap();
+        assert_eq!(vec![true, false, true, true, false, false, false, false, false, true],
+                   list.iter().collect::<Vec<bool>>());
+
+        let message = build_message(|root| {
+            let mut list: super::Builder<bool> = root.initn_as(10);
+            for &idx in &[0, 2, 3, 9] {
+                list.set(idx, true);
+            }
+        });
+        assert_eq!(&fixture[..], message.get_segments_for_output()[0]);
+    }
+}
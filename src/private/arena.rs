@@ -22,6 +22,7 @@
 use std::cell::Cell;
 use std::collections::HashMap;
 use std::mem;
+use std::ptr;
 use std::rc::Rc;
 use std::slice;
 use std::u64;
@@ -41,6 +42,7 @@ pub struct SegmentReader {
     pub ptr: *const Word,
     pub size: WordCount32,
     pub read_limiter: Rc<ReadLimiter>,
+    pub utf8_policy: message::Utf8Policy,
 }
 
 impl SegmentReader {
@@ -83,6 +85,7 @@ impl SegmentBuilder {
                 ptr: unsafe {mem::transmute(ptr)},
                 size: size,
                 read_limiter: limiter,
+                utf8_policy: message::Utf8Policy::Strict,
             },
             id: id,
             pos: ptr,
@@ -134,6 +137,15 @@ impl SegmentBuilder {
     pub fn currently_allocated<'a>(&'a self) -> &'a [Word] {
         unsafe { slice::from_raw_parts(self.get_ptr_unchecked(0), self.current_size() as usize) }
     }
+
+    /// Zeroes the words allocated beyond the first `size` and rewinds the segment so that
+    /// they can be allocated again.
+    pub fn rewind(&mut self, size: WordCount32) {
+        assert!(size <= self.current_size());
+        let ptr = self.get_ptr_unchecked(size);
+        unsafe { ptr::write_bytes(ptr, 0, (self.current_size() - size) as usize); }
+        self.pos = ptr;
+    }
 }
 
 pub struct ReadLimiter {
@@ -180,6 +192,7 @@ impl ReaderArena {
             ptr: unsafe { segment0.get_unchecked(0) },
             size: segment0.len() as u32,
             read_limiter: limiter.clone(),
+            utf8_policy: options.utf8_policy,
         };
 
         let mut arena = Box::new(ReaderArena {
@@ -213,7 +226,8 @@ impl ReaderArena {
                 arena: ArenaPtr::Reader(&mut *self),
                 ptr: unsafe { new_segment.get_unchecked(0) },
                 size: new_segment.len() as u32,
-                read_limiter: cloned_limiter
+                read_limiter: cloned_limiter,
+                utf8_policy: self.segment0.utf8_policy,
             };
             self.more_segments.insert(id, Box::new(new_segment_reader));
             Ok(&*self.more_segments[&id])
@@ -230,14 +244,35 @@ pub struct BuilderArena {
     allocator: &'static mut Allocator,
     pub segment0: SegmentBuilder,
     pub more_segments: Vec<Box<SegmentBuilder>>,
+    spare_segments: Vec<Box<SegmentBuilder>>,
     pub cap_table: Vec<Option<Box<ClientHook+Send>>>,
     pub dummy_limiter: Rc<ReadLimiter>,
+
+    //# Memory for segments that the allocator could not provide. Building carries on in these
+    //# so that nothing is written out of bounds, and `allocation_failed()` reports it.
+    fallback_space: Vec<Vec<Word>>,
+}
+
+/// Gets a segment of at least `minimum_size` words from `allocator`, or from the heap if the
+/// allocator cannot provide one.
+fn allocate_segment_space(allocator: &mut Allocator, fallback_space: &mut Vec<Vec<Word>>,
+                          minimum_size: WordCount32) -> (*mut Word, WordCount32) {
+    match allocator.try_allocate_segment(minimum_size) {
+        Some(result) => result,
+        None => {
+            let mut space = Word::allocate_zeroed_vec(minimum_size as usize);
+            let ptr = space.as_mut_ptr();
+            fallback_space.push(space);
+            (ptr, minimum_size)
+        }
+    }
 }
 
 impl BuilderArena  {
     pub fn new(allocator: &'static mut Allocator) -> Box<BuilderArena> {
         let limiter = Rc::new(ReadLimiter::new(u64::MAX));
-        let (first_segment, num_words) = allocator.allocate_segment(2);
+        let mut fallback_space = Vec::new();
+        let (first_segment, num_words) = allocate_segment_space(allocator, &mut fallback_space, 2);
 
         let mut result = Box::new(BuilderArena {
             allocator: allocator,
@@ -246,13 +281,16 @@ impl BuilderArena  {
                     ptr: first_segment,
                     size: num_words,
                     arena: ArenaPtr::Null,
-                    read_limiter: limiter.clone()},
+                    read_limiter: limiter.clone(),
+                    utf8_policy: message::Utf8Policy::Strict},
                 id: 0,
                 pos: first_segment,
             },
             more_segments: Vec::new(),
+            spare_segments: Vec::new(),
             cap_table: Vec::new(),
             dummy_limiter: limiter,
+            fallback_space: fallback_space,
         });
 
         let arena_ptr = ArenaPtr::Builder(&mut *result);
@@ -291,9 +329,21 @@ impl BuilderArena  {
                     }
                 }};
 
-            let (words, size) = self.allocator.allocate_segment(amount);
-            let mut new_builder = Box::new(SegmentBuilder::new(self, self.dummy_limiter.clone(),
-                                                               id as u32, words, size));
+            //# Prefer a segment left over from before the last reset.
+            let spare = self.spare_segments.iter().position(|segment| segment.reader.size >= amount);
+            let mut new_builder = match spare {
+                Some(index) => {
+                    let mut segment = self.spare_segments.remove(index);
+                    segment.id = id as u32;
+                    segment
+                }
+                None => {
+                    let (words, size) =
+                        allocate_segment_space(self.allocator, &mut self.fallback_space, amount);
+                    Box::new(SegmentBuilder::new(self, self.dummy_limiter.clone(),
+                                                 id as u32, words, size))
+                }
+            };
             let builder_ptr: *mut SegmentBuilder = &mut *new_builder;
 
             self.more_segments.push(new_builder);
@@ -329,6 +379,56 @@ impl BuilderArena  {
         &self.cap_table
     }
 
+    fn is_fallback(&self, segment: &SegmentBuilder) -> bool {
+        self.fallback_space.iter().any(|space| space.as_ptr() == segment.reader.ptr)
+    }
+
+    /// Returns true if any segment in use had to be allocated on the heap because the allocator
+    /// could not provide it.
+    pub fn allocation_failed(&self) -> bool {
+        self.is_fallback(&self.segment0) ||
+            self.more_segments.iter().any(|segment| self.is_fallback(segment))
+    }
+
+    /// Discards the message contents while keeping every segment around for reuse.
+    pub fn reset(&mut self) {
+        self.rewind(&[0], 0);
+    }
+
+    /// Gets the number of words in use in each segment.
+    pub fn segment_sizes(&self) -> Vec<WordCount32> {
+        let mut result = vec![self.segment0.current_size()];
+        result.extend(self.more_segments.iter().map(|segment| segment.current_size()));
+        result
+    }
+
+    /// Rolls allocation back to the given per-segment sizes, as previously returned by
+    /// `segment_sizes()`, and truncates the cap table to `cap_count` entries. Segments that
+    /// become empty are kept around for reuse.
+    pub fn rewind(&mut self, sizes: &[WordCount32], cap_count: usize) {
+        assert!(sizes.len() >= 1 && sizes.len() <= 1 + self.more_segments.len(),
+                "cannot rewind to a state with {} segments from one with {}",
+                sizes.len(), 1 + self.more_segments.len());
+        while self.more_segments.len() + 1 > sizes.len() {
+            let mut segment = self.more_segments.pop().unwrap();
+            segment.rewind(0);
+            self.spare_segments.insert(0, segment);
+        }
+        self.segment0.rewind(sizes[0]);
+        for (segment, &size) in self.more_segments.iter_mut().zip(sizes[1..].iter()) {
+            segment.rewind(size);
+        }
+        self.cap_table.truncate(cap_count);
+
+        //# Don't hand out fallback memory again; the allocator should get another chance.
+        let spare_segments = ::std::mem::replace(&mut self.spare_segments, Vec::new());
+        self.spare_segments = spare_segments.into_iter()
+            .filter(|segment| !self.is_fallback(segment)).collect();
+        let in_use: Vec<*const Word> = Some(self.segment0.reader.ptr).into_iter()
+            .chain(self.more_segments.iter().map(|segment| segment.reader.ptr)).collect();
+        self.fallback_space.retain(|space| in_use.contains(&space.as_ptr()));
+    }
+
     pub fn inject_cap(&mut self, cap: Box<ClientHook+Send>) -> u32 {
         self.cap_table.push(Some(cap));
         self.cap_table.len() as u32 - 1
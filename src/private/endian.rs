@@ -19,6 +19,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
 // THE SOFTWARE.
 
+/// A value stored in little-endian byte order, as it appears on the wire. All reads and
+/// writes of multi-byte values in a message go through `get` and `set`, which convert to and
+/// from the host's byte order, so messages are laid out identically on big-endian targets.
 #[repr(C)]
 pub struct WireValue<T> {
     value : T
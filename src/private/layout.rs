@@ -30,7 +30,7 @@ use private::endian::{WireValue, Endian};
 use private::mask::*;
 use private::units::*;
 use private::zero;
-use {MessageSize, Result, Word};
+use {Error, MessageSize, Result, Word};
 
 pub use self::ElementSize::{Void, Bit, Byte, TwoBytes, FourBytes, EightBytes, Pointer, InlineComposite};
 
@@ -360,6 +360,7 @@ mod wire_helpers {
     use private::layout::*;
     use private::units::*;
     use data;
+    use message::Utf8Policy;
     use text;
     use {Error, MessageSize, Result, Word};
 
@@ -515,9 +516,24 @@ mod wire_helpers {
             let pad: *const WirePointer = ptr as *const _;
 
             if !(**reff).is_double_far() {
+                //# A far pointer may not lead to another far pointer, which also rules out
+                //# chains and loops of them.
+                if (*pad).kind() == WirePointerKind::Far {
+                    return Err(Error::new_decode_error(
+                        "Far pointer landing pad is itself a far pointer.", None));
+                }
                 *reff = pad;
                 return Ok((*pad).target());
             } else {
+                if (*pad).kind() != WirePointerKind::Far || (*pad).is_double_far() {
+                    return Err(Error::new_decode_error(
+                        "First word of double-far landing pad must be a single far pointer.", None));
+                }
+                if (*pad.offset(1)).kind() == WirePointerKind::Far {
+                    return Err(Error::new_decode_error(
+                        "Double-far landing pad tag is a far pointer.", None));
+                }
+
                 //# Landing pad is another far pointer. It is
                 //# followed by a tag describing the pointed-to
                 //# object.
@@ -746,6 +762,160 @@ mod wire_helpers {
         Ok(result)
     }
 
+    pub unsafe fn is_canonical(segment: *const SegmentReader,
+                               reff: *const WirePointer,
+                               read_head: &mut *const Word,
+                               nesting_limit: i32) -> Result<bool> {
+        //# Checks that the object pointed to by `reff` starts exactly at `read_head` and that it
+        //# and all of its children are laid out in canonical form, advancing `read_head` past
+        //# everything that was consumed.
+
+        if (*reff).is_null() { return Ok(true); }
+
+        match (*reff).kind() {
+            WirePointerKind::Far | WirePointerKind::Other => Ok(false),
+            WirePointerKind::Struct => {
+                let reader = try!(read_struct_pointer(segment, reff, ptr::null(), nesting_limit));
+                if reader.data_size == 0 && reader.pointer_count == 0 {
+                    //# An empty struct must point at its own pointer.
+                    return Ok(reader.data as *const Word == reff as *const Word);
+                }
+
+                let mut data_trunc = false;
+                let mut ptr_trunc = false;
+                if !struct_data_is_canonical(&reader, read_head, &mut data_trunc, &mut ptr_trunc) {
+                    return Ok(false);
+                }
+                Ok(try!(struct_pointers_are_canonical(&reader, read_head)) && data_trunc && ptr_trunc)
+            }
+            WirePointerKind::List => {
+                let reader = try!(read_list_pointer(segment, reff, ptr::null(), Void, nesting_limit));
+                list_is_canonical(&reader, reff, read_head)
+            }
+        }
+    }
+
+    unsafe fn struct_data_is_canonical(reader: &StructReader,
+                                       read_head: &mut *const Word,
+                                       data_trunc: &mut bool,
+                                       ptr_trunc: &mut bool) -> bool {
+        if reader.data as *const Word != *read_head {
+            return false;
+        }
+        if reader.data_size % BITS_PER_WORD as u32 != 0 {
+            //# Mixed pointer/data section.
+            return false;
+        }
+        let data_words = reader.data_size / BITS_PER_WORD as u32;
+
+        //# Record whether the trailing data word and trailing pointer are nonzero, i.e. whether
+        //# the struct has been properly truncated.
+        *data_trunc = data_words == 0 || reader.get_data_field::<u64>(data_words as usize - 1) != 0;
+        *ptr_trunc = reader.pointer_count == 0 ||
+            !reader.get_pointer_field(reader.pointer_count as usize - 1).is_null();
+
+        *read_head = (*read_head).offset(data_words as isize + reader.pointer_count as isize);
+        true
+    }
+
+    unsafe fn struct_pointers_are_canonical(reader: &StructReader,
+                                            ptr_head: &mut *const Word) -> Result<bool> {
+        for i in 0..reader.pointer_count as isize {
+            if !try!(is_canonical(reader.segment, reader.pointers.offset(i), ptr_head,
+                                  reader.nesting_limit)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    unsafe fn list_is_canonical(reader: &ListReader,
+                                reff: *const WirePointer,
+                                read_head: &mut *const Word) -> Result<bool> {
+        match (*reff).list_ref().element_size() {
+            InlineComposite => {
+                //# The tag word comes first.
+                *read_head = (*read_head).offset(POINTER_SIZE_IN_WORDS as isize);
+                if reader.ptr as *const Word != *read_head {
+                    return Ok(false);
+                }
+                if reader.struct_data_size % BITS_PER_WORD as u32 != 0 {
+                    return Ok(false);
+                }
+                let element_words = reader.struct_data_size / BITS_PER_WORD as u32 +
+                    reader.struct_pointer_count as u32 * WORDS_PER_POINTER as u32;
+                let total_words = element_words as u64 * reader.element_count as u64;
+                if total_words != (*reff).list_ref().inline_composite_word_count() as u64 {
+                    return Ok(false);
+                }
+                if element_words == 0 {
+                    return Ok(true);
+                }
+
+                let list_end = (*read_head).offset(total_words as isize);
+                let mut pointer_head = list_end;
+                let mut list_data_trunc = false;
+                let mut list_ptr_trunc = false;
+                for i in 0..reader.element_count {
+                    let element = reader.get_struct_element(i);
+                    let mut data_trunc = false;
+                    let mut ptr_trunc = false;
+                    if !struct_data_is_canonical(&element, read_head, &mut data_trunc, &mut ptr_trunc) ||
+                        !try!(struct_pointers_are_canonical(&element, &mut pointer_head)) {
+                        return Ok(false);
+                    }
+                    list_data_trunc |= data_trunc;
+                    list_ptr_trunc |= ptr_trunc;
+                }
+                *read_head = pointer_head;
+                Ok(list_data_trunc && list_ptr_trunc)
+            }
+            Pointer => {
+                if reader.ptr as *const Word != *read_head {
+                    return Ok(false);
+                }
+                *read_head = (*read_head).offset(reader.element_count as isize);
+                for i in 0..reader.element_count {
+                    if !try!(is_canonical(reader.segment, reader.get_pointer_element(i).pointer,
+                                          read_head, reader.nesting_limit)) {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            element_size => {
+                if reader.ptr as *const Word != *read_head {
+                    return Ok(false);
+                }
+
+                //# Any padding after the last element must be zero.
+                let bit_size = reader.element_count as u64 * data_bits_per_element(element_size) as u64;
+                let mut byte_read_head: *const u8 =
+                    (*read_head as *const u8).offset((bit_size / BITS_PER_BYTE as u64) as isize);
+                let read_head_end: *const Word = (*read_head).offset(round_bits_up_to_words(bit_size) as isize);
+
+                let leftover_bits = bit_size % BITS_PER_BYTE as u64;
+                if leftover_bits > 0 {
+                    let mask: u8 = !((1u8 << leftover_bits) - 1);
+                    if mask & *byte_read_head != 0 {
+                        return Ok(false);
+                    }
+                    byte_read_head = byte_read_head.offset(1);
+                }
+
+                while byte_read_head != read_head_end as *const u8 {
+                    if *byte_read_head != 0 {
+                        return Ok(false);
+                    }
+                    byte_read_head = byte_read_head.offset(1);
+                }
+
+                *read_head = read_head_end;
+                Ok(true)
+            }
+        }
+    }
+
     pub unsafe fn transfer_pointer(dst_segment: *mut SegmentBuilder, dst: *mut WirePointer,
                                    src_segment: *mut SegmentBuilder, src: *mut WirePointer) {
         //# Make *dst point to the same object as *src. Both must
@@ -763,7 +933,8 @@ mod wire_helpers {
 
         if (*src).is_null() {
             ::std::ptr::write_bytes(dst, 0, 1);
-        } else if (*src).kind() == WirePointerKind::Far {
+        } else if (*src).kind() == WirePointerKind::Far || (*src).kind() == WirePointerKind::Other {
+            //# Far and capability pointers do not depend on their own location.
             ::std::ptr::copy_nonoverlapping(src, dst, 1);
         } else {
             transfer_pointer_split(dst_segment, dst, src_segment, src, (*src).mut_target());
@@ -776,7 +947,9 @@ mod wire_helpers {
         // Like the other transfer_pointer, but splits src into a tag and a
         // target. Particularly useful for OrphanBuilder.
 
-        if dst_segment == src_segment {
+        if (*src_tag).kind() == WirePointerKind::Struct && (*src_tag).struct_ref().word_size() == 0 {
+            (*dst).set_kind_and_target_for_empty_struct();
+        } else if dst_segment == src_segment {
             //# Same segment, so create a direct pointer.
             (*dst).set_kind_and_target((*src_tag).kind(), src_ptr);
 
@@ -789,7 +962,17 @@ mod wire_helpers {
             match (*src_segment).allocate(1) {
                 None => {
                     //# Darn, need a double-far.
-                    panic!("unimplemented");
+                    let (far_segment, landing_pad_word) = (*(*src_segment).get_arena()).allocate(2);
+                    let landing_pad: *mut WirePointer = landing_pad_word as *mut _;
+                    (*landing_pad).set_far(false, (*src_segment).get_word_offset_to(src_ptr));
+                    (*landing_pad).mut_far_ref().set((*src_segment).get_segment_id());
+
+                    let tag = landing_pad.offset(1);
+                    (*tag).set_kind_with_zero_offset((*src_tag).kind());
+                    ::std::ptr::copy_nonoverlapping(&(*src_tag).upper32bits, &mut (*tag).upper32bits, 1);
+
+                    (*dst).set_far(true, (*far_segment).get_word_offset_to(landing_pad_word));
+                    (*dst).mut_far_ref().set((*far_segment).get_segment_id());
                 }
                 Some(landing_pad_word) => {
                     //# Simple landing pad is just a pointer.
@@ -960,6 +1143,100 @@ mod wire_helpers {
         }
     }
 
+    /// Gets the element size of the list that `reff` points to and, for struct lists, the size
+    /// of each struct.
+    pub unsafe fn get_list_layout(mut reff: *mut WirePointer,
+                                  mut segment: *mut SegmentBuilder) -> Result<(ElementSize, StructSize)> {
+        if (*reff).is_null() {
+            return Err(Error::new_decode_error("Expected a list but found a null pointer.", None));
+        }
+        let ref_target = (*reff).mut_target();
+        let ptr = try!(follow_builder_fars(&mut reff, ref_target, &mut segment));
+        if (*reff).kind() != WirePointerKind::List {
+            return Err(Error::new_decode_error("Expected a list but found a non-list pointer.", None));
+        }
+        let element_size = (*reff).list_ref().element_size();
+        if element_size == InlineComposite {
+            let tag: *const WirePointer = ptr as *const _;
+            Ok((element_size, StructSize { data: (*tag).struct_ref().data_size.get(),
+                                           pointers: (*tag).struct_ref().ptr_count.get() }))
+        } else {
+            Ok((element_size, StructSize { data: 0, pointers: 0 }))
+        }
+    }
+
+    /// Shortens the list that `reff` points to, in place. The elements that are cut off are
+    /// zeroed, but their space is not reclaimed.
+    pub unsafe fn truncate_list_pointer(mut reff: *mut WirePointer,
+                                        mut segment: *mut SegmentBuilder,
+                                        new_len: ElementCount32) -> Result<()> {
+        if (*reff).is_null() {
+            if new_len == 0 {
+                return Ok(());
+            }
+            return Err(Error::new_decode_error("Cannot truncate a list to a greater length.", None));
+        }
+        let ref_target = (*reff).mut_target();
+        let ptr = try!(follow_builder_fars(&mut reff, ref_target, &mut segment));
+        if (*reff).kind() != WirePointerKind::List {
+            return Err(Error::new_decode_error(
+                "Called truncate_list() but existing pointer is not a list.", None));
+        }
+
+        let element_size = (*reff).list_ref().element_size();
+        if element_size == InlineComposite {
+            let tag: *mut WirePointer = ptr as *mut _;
+            let count = (*tag).inline_composite_list_element_count();
+            if new_len > count {
+                return Err(Error::new_decode_error("Cannot truncate a list to a greater length.", None));
+            }
+            let data_size = (*tag).struct_ref().data_size.get();
+            let pointer_count = (*tag).struct_ref().ptr_count.get();
+            let words_per_element = (*tag).struct_ref().word_size();
+
+            let cut: *mut Word = ptr.offset((POINTER_SIZE_IN_WORDS as u32 + new_len * words_per_element) as isize);
+            let mut pos = cut;
+            for _ in new_len..count {
+                pos = pos.offset(data_size as isize);
+                for _ in 0..pointer_count {
+                    zero_object(segment, pos as *mut WirePointer);
+                    pos = pos.offset(POINTER_SIZE_IN_WORDS as isize);
+                }
+            }
+            ptr::write_bytes(cut, 0, ((count - new_len) * words_per_element) as usize);
+
+            (*reff).mut_list_ref().set_inline_composite(new_len * words_per_element);
+            (*tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct, new_len);
+        } else {
+            let count = (*reff).list_ref().element_count();
+            if new_len > count {
+                return Err(Error::new_decode_error("Cannot truncate a list to a greater length.", None));
+            }
+            if element_size == Pointer {
+                let pointers: *mut WirePointer = ptr as *mut _;
+                for i in new_len..count {
+                    zero_object(segment, pointers.offset(i as isize));
+                }
+                ptr::write_bytes(pointers.offset(new_len as isize), 0, (count - new_len) as usize);
+            } else {
+                let bits = data_bits_per_element(element_size) as u64;
+                let bytes: *mut u8 = ptr as *mut _;
+                let end_bit = count as u64 * bits;
+                let mut bit = new_len as u64 * bits;
+                while bit % BITS_PER_BYTE as u64 != 0 && bit < end_bit {
+                    *bytes.offset((bit / BITS_PER_BYTE as u64) as isize) &= !(1 << (bit % BITS_PER_BYTE as u64));
+                    bit += 1;
+                }
+                if bit < end_bit {
+                    ptr::write_bytes(bytes.offset((bit / BITS_PER_BYTE as u64) as isize), 0,
+                                     ((end_bit - bit + 7) / BITS_PER_BYTE as u64) as usize);
+                }
+            }
+            (*reff).mut_list_ref().set(element_size, new_len);
+        }
+        Ok(())
+    }
+
     #[inline]
     pub unsafe fn get_writable_list_pointer<'a>(orig_ref: *mut WirePointer,
                                                 orig_segment: *mut SegmentBuilder,
@@ -1233,6 +1510,7 @@ mod wire_helpers {
         return SegmentAnd {
             segment: segment,
             value: text::Builder::new(slice::from_raw_parts_mut(ptr as *mut _, size as usize), 0).unwrap()
+                .with_list_ref(&mut *(*reff).mut_list_ref())
         }
     }
 
@@ -1254,7 +1532,7 @@ mod wire_helpers {
                                                 default_size: ByteCount32) -> Result<text::Builder<'a>> {
         if (*reff).is_null() {
             if default_size == 0 {
-                return text::Builder::new(slice::from_raw_parts_mut(ptr::null_mut(), 0), 0);
+                return text::Builder::new(&mut [], 0);
             } else {
                 let _builder = init_text_pointer(reff, segment, default_size).value;
                 unimplemented!()
@@ -1280,7 +1558,36 @@ mod wire_helpers {
         }
 
         // Subtract 1 from the size for the NUL terminator.
-        return text::Builder::new(slice::from_raw_parts_mut(cptr, (count - 1) as usize), count - 1);
+        let builder = try!(text::Builder::new(slice::from_raw_parts_mut(cptr, (count - 1) as usize), count - 1));
+        return Ok(builder.with_list_ref(&mut *(*reff).mut_list_ref()));
+    }
+
+    pub unsafe fn resize_text_pointer<'a>(reff: *mut WirePointer,
+                                          segment: *mut SegmentBuilder,
+                                          size: ByteCount32) -> Result<SegmentAnd<text::Builder<'a>>> {
+        let old = try!(get_writable_text_pointer(reff, segment, ptr::null(), 0));
+        let mut keep = ::std::cmp::min(old.len(), size as usize);
+        while !old.is_char_boundary(keep) {
+            keep -= 1;
+        }
+        let new_size = if (size as usize) < old.len() { keep as u32 } else { size };
+
+        // Allocate the new text while the old one is still intact, so that its contents can be
+        // copied straight across. Nulling the pointer first keeps `allocate()` from zeroing the
+        // old object; it is zeroed afterwards by briefly restoring the old pointer.
+        let old_ref = ptr::read(reff);
+        ptr::write_bytes(reff, 0u8, 1);
+        let mut allocation = init_text_pointer(reff, segment, new_size);
+        allocation.value.push_str(&old[..keep]);
+
+        let new_ref = ptr::read(reff);
+        if !old_ref.is_null() {
+            ptr::write(reff, old_ref);
+            zero_object(segment, reff);
+            ptr::write(reff, new_ref);
+        }
+
+        Ok(allocation)
     }
 
     #[inline]
@@ -1340,24 +1647,48 @@ mod wire_helpers {
 
     pub unsafe fn set_struct_pointer<'a>(mut segment: *mut SegmentBuilder,
                                          mut reff: *mut WirePointer,
-                                         value: StructReader) -> Result<SegmentAnd<*mut Word>> {
-        let data_size: WordCount32 = round_bits_up_to_words(value.data_size as u64);
-        let total_size: WordCount32 = data_size + value.pointer_count as u32 * WORDS_PER_POINTER as u32;
+                                         value: StructReader,
+                                         canonical: bool) -> Result<SegmentAnd<*mut Word>> {
+        let mut data_bytes: ByteCount32 = round_bits_up_to_bytes(value.data_size as u64);
+        let mut pointer_count = value.pointer_count;
+
+        if canonical {
+            //# Truncate trailing zero bytes of the data section and trailing null pointers.
+            if value.data_size == 1 {
+                if !value.get_bool_field(0) {
+                    data_bytes = 0;
+                }
+            } else {
+                let data = value.data as *const u8;
+                while data_bytes > 0 && *data.offset(data_bytes as isize - 1) == 0 {
+                    data_bytes -= 1;
+                }
+            }
+
+            while pointer_count > 0 && (*value.pointers.offset(pointer_count as isize - 1)).is_null() {
+                pointer_count -= 1;
+            }
+        }
+
+        let data_size: WordCount32 = round_bytes_up_to_words(data_bytes);
+        let total_size: WordCount32 = data_size + pointer_count as u32 * WORDS_PER_POINTER as u32;
 
         let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::Struct);
-        (*reff).mut_struct_ref().set(data_size as u16, value.pointer_count);
+        (*reff).mut_struct_ref().set(data_size as u16, pointer_count);
 
         if value.data_size == 1 {
-            *(ptr as *mut u8) = value.get_bool_field(0) as u8
+            if data_bytes != 0 {
+                *(ptr as *mut u8) = value.get_bool_field(0) as u8
+            }
         } else {
-            ptr::copy_nonoverlapping::<Word>(value.data as *const _, ptr,
-                                             value.data_size as usize / BITS_PER_WORD);
+            ptr::copy_nonoverlapping::<u8>(value.data as *const _, ptr as *mut u8,
+                                           data_bytes as usize);
         }
 
         let pointer_section: *mut WirePointer = ptr.offset(data_size as isize) as *mut _;
-        for i in 0..value.pointer_count as isize {
+        for i in 0..pointer_count as isize {
             try!(copy_pointer(segment, pointer_section.offset(i), value.segment, value.pointers.offset(i),
-                              value.nesting_limit));
+                              value.nesting_limit, canonical));
         }
 
         Ok(SegmentAnd { segment: segment, value: ptr })
@@ -1371,75 +1702,114 @@ mod wire_helpers {
 
     pub unsafe fn set_list_pointer<'a>(mut segment: *mut SegmentBuilder,
                                        mut reff: *mut WirePointer,
-                                       value: ListReader) -> Result<SegmentAnd<*mut Word>> {
-        let total_size = round_bits_up_to_words((value.element_count * value.step) as u64);
+                                       value: ListReader,
+                                       canonical: bool) -> Result<SegmentAnd<*mut Word>> {
+        if value.step > BITS_PER_WORD as u32 {
+            return set_struct_list_pointer(segment, reff, value, canonical);
+        }
 
-        if value.step <= BITS_PER_WORD as u32 {
-            //# List of non-structs.
-            let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::List);
-
-            if value.struct_pointer_count == 1 {
-                //# List of pointers.
-                (*reff).mut_list_ref().set(Pointer, value.element_count);
-                for i in 0.. value.element_count as isize {
-                    try!(copy_pointer(segment,
-                                      (ptr as *mut _).offset(i),
-                                      value.segment,
-                                      (value.ptr as *const _).offset(i),
-                                      value.nesting_limit));
-                }
-            } else {
-                //# List of data.
-                let element_size = match value.step {
-                    0 => Void,
-                    1 => Bit,
-                    8 => Byte,
-                    16 => TwoBytes,
-                    32 => FourBytes,
-                    64 => EightBytes,
-                    _ => { panic!("invalid list step size: {}", value.step) }
-                };
-
-                (*reff).mut_list_ref().set(element_size, value.element_count);
-                ::std::ptr::copy_nonoverlapping(value.ptr as *const Word, ptr, total_size as usize);
-            }
-
-            Ok(SegmentAnd { segment: segment, value: ptr })
+        //# List of non-structs.
+        let total_size = round_bits_up_to_words((value.element_count * value.step) as u64);
+        let ptr = allocate(&mut reff, &mut segment, total_size, WirePointerKind::List);
+
+        if value.struct_pointer_count == 1 {
+            //# List of pointers.
+            (*reff).mut_list_ref().set(Pointer, value.element_count);
+            for i in 0.. value.element_count as isize {
+                try!(copy_pointer(segment,
+                                  (ptr as *mut _).offset(i),
+                                  value.segment,
+                                  (value.ptr as *const _).offset(i),
+                                  value.nesting_limit,
+                                  canonical));
+            }
         } else {
-            //# List of structs.
-            let ptr = allocate(&mut reff, &mut segment, total_size + POINTER_SIZE_IN_WORDS as u32, WirePointerKind::List);
-            (*reff).mut_list_ref().set_inline_composite(total_size);
+            //# List of data.
+            let element_size = match value.step {
+                0 => Void,
+                1 => Bit,
+                8 => Byte,
+                16 => TwoBytes,
+                32 => FourBytes,
+                64 => EightBytes,
+                _ => { panic!("invalid list step size: {}", value.step) }
+            };
 
-            let data_size = round_bits_up_to_words(value.struct_data_size as u64);
-            let pointer_count = value.struct_pointer_count;
+            (*reff).mut_list_ref().set(element_size, value.element_count);
+            let data_bits = value.element_count as u64 * value.step as u64;
+            let data_bytes = round_bits_up_to_bytes(data_bits);
+            ptr::copy_nonoverlapping(value.ptr as *const u8, ptr as *mut u8, data_bytes as usize);
 
-            let tag: *mut WirePointer = ptr as *mut _;
-            (*tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct, value.element_count);
-            (*tag).mut_struct_ref().set(data_size as u16, pointer_count);
-            let mut dst = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+            if canonical && data_bits % 8 != 0 {
+                //# Clear the padding bits that follow the last element of a bit list.
+                *(ptr as *mut u8).offset(data_bytes as isize - 1) &= (1u8 << (data_bits % 8)) - 1;
+            }
+        }
 
-            let mut src: *const Word = value.ptr as *const _;
-            for _ in 0.. value.element_count {
-                ::std::ptr::copy_nonoverlapping(src, dst,
-                                                value.struct_data_size as usize / BITS_PER_WORD);
-                dst = dst.offset(data_size as isize);
-                src = src.offset(data_size as isize);
+        Ok(SegmentAnd { segment: segment, value: ptr })
+    }
 
-                for _ in 0..pointer_count {
-                    try!(copy_pointer(segment, dst as *mut _,
-                                      value.segment, src as *const _,
-                                      value.nesting_limit));
-                    dst = dst.offset(POINTER_SIZE_IN_WORDS as isize);
-                    src = src.offset(POINTER_SIZE_IN_WORDS as isize);
+    pub unsafe fn set_struct_list_pointer<'a>(mut segment: *mut SegmentBuilder,
+                                              mut reff: *mut WirePointer,
+                                              value: ListReader,
+                                              canonical: bool) -> Result<SegmentAnd<*mut Word>> {
+        let decl_data_size = round_bits_up_to_words(value.struct_data_size as u64);
+        let decl_pointer_count = value.struct_pointer_count;
+
+        let (data_size, pointer_count) = if canonical {
+            //# Every element gets the smallest layout that fits all of their truncated sections.
+            let mut data_size: WordCount32 = 0;
+            let mut pointer_count: WirePointerCount16 = 0;
+            for i in 0..value.element_count {
+                let element = value.get_struct_element(i);
+                let data = element.data as *const u8;
+                let mut data_bytes = decl_data_size * BYTES_PER_WORD as u32;
+                while data_bytes > 0 && *data.offset(data_bytes as isize - 1) == 0 {
+                    data_bytes -= 1;
                 }
+                data_size = ::std::cmp::max(data_size, round_bytes_up_to_words(data_bytes));
+
+                let mut element_pointer_count = decl_pointer_count;
+                while element_pointer_count > 0 &&
+                    (*element.pointers.offset(element_pointer_count as isize - 1)).is_null() {
+                    element_pointer_count -= 1;
+                }
+                pointer_count = ::std::cmp::max(pointer_count, element_pointer_count);
             }
-            Ok(SegmentAnd { segment: segment, value: ptr })
+            (data_size, pointer_count)
+        } else {
+            (decl_data_size, decl_pointer_count)
+        };
+
+        let total_size = (data_size + pointer_count as u32 * WORDS_PER_POINTER as u32) * value.element_count;
+        let ptr = allocate(&mut reff, &mut segment, total_size + POINTER_SIZE_IN_WORDS as u32, WirePointerKind::List);
+        (*reff).mut_list_ref().set_inline_composite(total_size);
+
+        let tag: *mut WirePointer = ptr as *mut _;
+        (*tag).set_kind_and_inline_composite_list_element_count(WirePointerKind::Struct, value.element_count);
+        (*tag).mut_struct_ref().set(data_size as u16, pointer_count);
+        let mut dst = ptr.offset(POINTER_SIZE_IN_WORDS as isize);
+
+        let mut src: *const Word = value.ptr as *const _;
+        for _ in 0.. value.element_count {
+            ptr::copy_nonoverlapping(src, dst, data_size as usize);
+            dst = dst.offset(data_size as isize);
+            src = src.offset(decl_data_size as isize);
+
+            for i in 0..pointer_count as isize {
+                try!(copy_pointer(segment, (dst as *mut WirePointer).offset(i),
+                                  value.segment, (src as *const WirePointer).offset(i),
+                                  value.nesting_limit, canonical));
+            }
+            dst = dst.offset(pointer_count as isize * WORDS_PER_POINTER as isize);
+            src = src.offset(decl_pointer_count as isize * WORDS_PER_POINTER as isize);
         }
+        Ok(SegmentAnd { segment: segment, value: ptr })
     }
 
     pub unsafe fn copy_pointer(dst_segment: *mut SegmentBuilder, dst: *mut WirePointer,
                                mut src_segment: *const SegmentReader, mut src: *const WirePointer,
-                               nesting_limit: i32) -> Result<SegmentAnd<*mut Word>> {
+                               nesting_limit: i32, canonical: bool) -> Result<SegmentAnd<*mut Word>> {
         let src_target = (*src).target();
 
         if (*src).is_null() {
@@ -1468,7 +1838,8 @@ mod wire_helpers {
                         pointers: ptr.offset((*src).struct_ref().data_size.get() as isize) as *mut _,
                         data_size: (*src).struct_ref().data_size.get() as u32 * BITS_PER_WORD as u32,
                         pointer_count: (*src).struct_ref().ptr_count.get(),
-                        nesting_limit: nesting_limit - 1 });
+                        nesting_limit: nesting_limit - 1 },
+                    canonical);
 
             }
             WirePointerKind::List => {
@@ -1505,7 +1876,7 @@ mod wire_helpers {
                         try!(amplified_read(src_segment, element_count as u64));
                     }
 
-                    return set_list_pointer(
+                    return set_struct_list_pointer(
                         dst_segment, dst,
                         ListReader {
                             marker: ::std::marker::PhantomData,
@@ -1516,7 +1887,8 @@ mod wire_helpers {
                             struct_data_size: (*tag).struct_ref().data_size.get() as u32 * BITS_PER_WORD as u32,
                             struct_pointer_count: (*tag).struct_ref().ptr_count.get(),
                             nesting_limit: nesting_limit - 1
-                        })
+                        },
+                        canonical)
                 } else {
                     let data_size = data_bits_per_element(element_size);
                     let pointer_count = pointers_per_element(element_size);
@@ -1543,7 +1915,8 @@ mod wire_helpers {
                             struct_data_size: data_size,
                             struct_pointer_count: pointer_count as u16,
                             nesting_limit: nesting_limit - 1
-                        })
+                        },
+                        canonical)
                 }
             }
             WirePointerKind::Far => {
@@ -1616,7 +1989,8 @@ mod wire_helpers {
                                           reff: *const WirePointer,
                                           _nesting_limit: i32) -> Result<Box<ClientHook+Send>> {
         if (*reff).is_null() {
-            panic!("broken cap factory is unimplemented");
+            return Err(Error::new_decode_error(
+                "Message contains null capability pointer.", None));
         } else if !(*reff).is_capability() {
             return Err(Error::new_decode_error(
                 "Message contains non-capability pointer where capability pointer was expected.", None));
@@ -1782,14 +2156,26 @@ mod wire_helpers {
     }
 
     #[inline]
-    pub unsafe fn read_text_pointer<'a>(mut segment: *const SegmentReader,
-                                        mut reff: *const WirePointer,
+    pub unsafe fn read_text_pointer<'a>(segment: *const SegmentReader,
+                                        reff: *const WirePointer,
                                         default_value: *const Word,
                                         default_size: ByteCount32) -> Result<text::Reader<'a>> {
+        let policy = if segment.is_null() { Utf8Policy::Strict } else { (*segment).utf8_policy };
+        text::new_reader_with_policy(try!(read_text_bytes(segment, reff, default_value, default_size)),
+                                     policy)
+    }
+
+    /// Like `read_text_pointer()`, but does not check that the text is valid UTF-8.
+    #[inline]
+    pub unsafe fn read_text_bytes<'a>(mut segment: *const SegmentReader,
+                                      mut reff: *const WirePointer,
+                                      default_value: *const Word,
+                                      default_size: ByteCount32) -> Result<&'a [u8]> {
         if (*reff).is_null() {
-            //   TODO?       if default_value.is_null() { default_value = &"" }
-            return text::new_reader(
-                slice::from_raw_parts(::std::mem::transmute(default_value), default_size as usize));
+            if default_size == 0 {
+                return Ok(&[]);
+            }
+            return Ok(slice::from_raw_parts(::std::mem::transmute(default_value), default_size as usize));
         }
 
         let ref_target = (*reff).target();
@@ -1822,7 +2208,7 @@ mod wire_helpers {
                 "Message contains text that is not NUL-terminated", None));
         }
 
-        Ok(try!(text::new_reader(slice::from_raw_parts(str_ptr, size as usize -1))))
+        Ok(slice::from_raw_parts(str_ptr, size as usize -1))
     }
 
     #[inline]
@@ -1863,6 +2249,14 @@ mod wire_helpers {
 static ZERO: u64 = 0;
 fn zero_pointer() -> *const WirePointer { &ZERO as *const _ as *const _ }
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PointerType {
+    Null,
+    Struct,
+    List,
+    Capability,
+}
+
 #[derive(Clone, Copy)]
 pub struct PointerReader<'a> {
     marker: ::std::marker::PhantomData<&'a ()>,
@@ -1933,6 +2327,15 @@ impl <'a> PointerReader<'a> {
         }
     }
 
+    /// Gets the bytes of a text field, excluding the NUL terminator, without checking that they
+    /// are valid UTF-8.
+    pub fn get_text_bytes(&self, default_value: *const Word, default_size: ByteCount32) -> Result<&'a [u8]> {
+        let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
+        unsafe {
+            wire_helpers::read_text_bytes(self.segment, reff, default_value, default_size)
+        }
+    }
+
     pub fn get_data(&self, default_value: *const Word, default_size: ByteCount32) -> Result<data::Reader<'a>> {
         let reff = if self.pointer.is_null() { zero_pointer() } else { self.pointer };
         unsafe {
@@ -1946,6 +2349,64 @@ impl <'a> PointerReader<'a> {
             wire_helpers::read_capability_pointer(self.segment, reff, self.nesting_limit)
         }
     }
+
+    pub fn is_canonical(&self, read_head: &mut *const Word) -> Result<bool> {
+        if self.pointer.is_null() {
+            Ok(true)
+        } else {
+            unsafe { wire_helpers::is_canonical(self.segment, self.pointer, read_head, self.nesting_limit) }
+        }
+    }
+
+    pub fn total_size(&self) -> Result<MessageSize> {
+        if self.pointer.is_null() {
+            Ok(MessageSize { word_count: 0, cap_count: 0 })
+        } else {
+            unsafe { wire_helpers::total_size(self.segment, self.pointer, self.nesting_limit) }
+        }
+    }
+
+    /// Gets the kind of object that this pointer points to, following any far pointers.
+    pub fn get_pointer_type(&self) -> Result<PointerType> {
+        if self.is_null() {
+            return Ok(PointerType::Null);
+        }
+        unsafe {
+            let mut reff: *const WirePointer = self.pointer;
+            let mut segment = self.segment;
+            let ref_target = (*reff).target();
+            try!(wire_helpers::follow_fars(&mut reff, ref_target, &mut segment));
+            match (*reff).kind() {
+                WirePointerKind::Struct => Ok(PointerType::Struct),
+                WirePointerKind::List => Ok(PointerType::List),
+                WirePointerKind::Other if (*reff).is_capability() => Ok(PointerType::Capability),
+                _ => Err(Error::new_decode_error("Unknown pointer type.", None)),
+            }
+        }
+    }
+
+    /// Compares the targets of two pointers by value. Structs are equal if their sections are
+    /// equal once truncated of trailing zeros and null pointers, and lists are compared element
+    /// by element, so the result does not depend on how either message is laid out.
+    /// Capabilities cannot be compared.
+    pub fn equals(&self, other: &PointerReader) -> Result<bool> {
+        let pointer_type = try!(self.get_pointer_type());
+        if pointer_type != try!(other.get_pointer_type()) {
+            return Ok(false);
+        }
+        match pointer_type {
+            PointerType::Null => Ok(true),
+            PointerType::Struct => {
+                try!(self.get_struct(::std::ptr::null())).equals(&try!(other.get_struct(::std::ptr::null())))
+            }
+            PointerType::List => {
+                try!(self.get_list(Void, ::std::ptr::null())).equals(&try!(other.get_list(Void, ::std::ptr::null())))
+            }
+            PointerType::Capability => {
+                Err(Error::new_decode_error("Cannot compare capabilities.", None))
+            }
+        }
+    }
 }
 
 pub struct PointerBuilder<'a> {
@@ -2047,16 +2508,27 @@ impl <'a> PointerBuilder<'a> {
         }
     }
 
+    /// Replaces the text that this pointer points to with a newly allocated text of `size` bytes,
+    /// copying over as much of the old contents as fits without splitting a char. When shrinking,
+    /// the new text is sized to the copied contents, so a `size` that falls inside a char yields
+    /// a slightly shorter text. The returned builder is positioned after the copied contents.
+    /// The old text is zeroed, but its space is not reclaimed.
+    pub fn resize_text(&self, size: ByteCount32) -> Result<text::Builder<'a>> {
+        unsafe {
+            Ok(try!(wire_helpers::resize_text_pointer(self.pointer, self.segment, size)).value)
+        }
+    }
+
     pub fn set_struct(&self, value: &StructReader) -> Result<()> {
         unsafe {
-            try!(wire_helpers::set_struct_pointer(self.segment, self.pointer, *value));
+            try!(wire_helpers::set_struct_pointer(self.segment, self.pointer, *value, false));
             Ok(())
         }
     }
 
     pub fn set_list(&self, value: &ListReader) -> Result<()> {
         unsafe {
-            try!(wire_helpers::set_list_pointer(self.segment, self.pointer, *value));
+            try!(wire_helpers::set_list_pointer(self.segment, self.pointer, *value, false));
             Ok(())
         }
     }
@@ -2067,6 +2539,23 @@ impl <'a> PointerBuilder<'a> {
         }
     }
 
+    /// Sets the text that this pointer points to from formatted arguments, without allocating an
+    /// intermediate `String`. The arguments are formatted twice: once to measure them and once to
+    /// write them, so a value whose formatting changes between the two passes results in an
+    /// error.
+    pub fn set_text_fmt(&self, args: ::std::fmt::Arguments) -> ::std::fmt::Result {
+        use std::fmt::Write;
+
+        let mut counter = ::util::ByteCounter(0);
+        try!(counter.write_fmt(args));
+        let mut text = self.init_text(counter.0 as ByteCount32);
+        try!(text.write_fmt(args));
+        if text.remaining() != 0 {
+            return Err(::std::fmt::Error);
+        }
+        Ok(())
+    }
+
     pub fn set_data(&self, value: &[u8]) {
         unsafe {
             wire_helpers::set_data_pointer(self.pointer, self.segment, value);
@@ -2079,7 +2568,7 @@ impl <'a> PointerBuilder<'a> {
         }
     }
 
-    pub fn copy_from(&self, other: PointerReader) -> Result<()> {
+    pub fn copy_from(&self, other: PointerReader, canonical: bool) -> Result<()> {
         if other.pointer.is_null()  {
             if !self.pointer.is_null() {
                 unsafe {
@@ -2090,7 +2579,7 @@ impl <'a> PointerBuilder<'a> {
         } else {
             unsafe {
                 try!(wire_helpers::copy_pointer(self.segment, self.pointer, other.segment, other.pointer,
-                                                other.nesting_limit));
+                                                other.nesting_limit, canonical));
             }
         }
         Ok(())
@@ -2113,6 +2602,101 @@ impl <'a> PointerBuilder<'a> {
                 nesting_limit: 0x7fffffff }
         }
     }
+
+    /// Shortens the list that this pointer points to, in place. See
+    /// `wire_helpers::truncate_list_pointer()`.
+    pub fn truncate_list(&self, new_len: ElementCount32) -> Result<()> {
+        unsafe {
+            wire_helpers::truncate_list_pointer(self.pointer, self.segment, new_len)
+        }
+    }
+
+    /// Changes the length of the list that this pointer points to. A shorter list is truncated in
+    /// place. A longer one is allocated anew in an orphan, the elements are copied over, and the
+    /// orphan is adopted, zeroing the old list. New elements are zero.
+    pub fn resize_list(&self, new_len: ElementCount32) -> Result<()> {
+        let (element_size, struct_size) = try!(unsafe {
+            wire_helpers::get_list_layout(self.pointer, self.segment)
+        });
+        let old = try!(self.as_reader().get_list(element_size, ptr::null()));
+        if new_len <= old.len() {
+            return self.truncate_list(new_len);
+        }
+
+        let orphan = self.init_orphan();
+        let new = if element_size == InlineComposite {
+            orphan.pointer.init_struct_list(new_len, struct_size)
+        } else {
+            orphan.pointer.init_list(element_size, new_len)
+        };
+        for i in 0..old.len() {
+            if element_size == Bit {
+                let value: bool = PrimitiveElement::get(&old, i);
+                PrimitiveElement::set(&new, i, value);
+            } else {
+                try!(new.get_struct_element(i).copy_content_from(&old.get_struct_element(i)));
+            }
+        }
+        self.adopt(orphan);
+        Ok(())
+    }
+
+    /// Allocates a new, null orphan in the same message as this pointer.
+    pub fn init_orphan(&self) -> OrphanBuilder<'a> {
+        unsafe {
+            let (segment, location) =
+                (*(*self.segment).get_arena()).allocate(POINTER_SIZE_IN_WORDS as u32);
+            OrphanBuilder { pointer: PointerBuilder::get_root(segment, location) }
+        }
+    }
+
+    /// Detaches the pointed-to object from this pointer, which becomes null.
+    pub fn disown(&self) -> OrphanBuilder<'a> {
+        let orphan = self.init_orphan();
+        unsafe {
+            wire_helpers::transfer_pointer(orphan.pointer.segment, orphan.pointer.pointer,
+                                           self.segment, self.pointer);
+            ptr::write_bytes(self.pointer, 0, 1);
+        }
+        orphan
+    }
+
+    /// Points this pointer at the orphan's object, zeroing whatever it pointed to before.
+    pub fn adopt(&self, orphan: OrphanBuilder<'a>) {
+        unsafe {
+            assert!((*self.segment).get_arena() == (*orphan.pointer.segment).get_arena(),
+                    "cannot adopt an orphan that belongs to a different message");
+            self.clear();
+            wire_helpers::transfer_pointer(self.segment, self.pointer,
+                                           orphan.pointer.segment, orphan.pointer.pointer);
+            ptr::write_bytes(orphan.pointer.pointer, 0, 1);
+        }
+    }
+}
+
+/// An object that is not reachable from the root of its message. The orphan keeps its object
+/// alive through a pointer word of its own, allocated in the message; that word is left zeroed
+/// behind once the object is adopted. Dropping an orphan zeroes its object.
+pub struct OrphanBuilder<'a> {
+    pointer: PointerBuilder<'a>,
+}
+
+impl <'a> OrphanBuilder<'a> {
+    pub fn is_null(&self) -> bool {
+        self.pointer.is_null()
+    }
+
+    pub fn as_pointer(&self) -> PointerBuilder<'a> {
+        PointerBuilder::get_root(self.pointer.segment, self.pointer.pointer as *mut _)
+    }
+}
+
+impl <'a> Drop for OrphanBuilder<'a> {
+    fn drop(&mut self) {
+        if !self.pointer.is_null() {
+            self.pointer.clear();
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -2186,6 +2770,34 @@ impl <'a> StructReader<'a>  {
     }
 
     #[inline]
+    fn get_data_byte(&self, index: ByteCount32) -> u8 {
+        if self.data_size == 1 {
+            if index == 0 { self.get_bool_field(0) as u8 } else { 0 }
+        } else if index < self.data_size / BITS_PER_BYTE as u32 {
+            unsafe { *self.data.offset(index as isize) }
+        } else {
+            0
+        }
+    }
+
+    /// Compares two structs by value. See `PointerReader::equals()`.
+    pub fn equals(&self, other: &StructReader) -> Result<bool> {
+        let data_bytes = wire_helpers::round_bits_up_to_bytes(::std::cmp::max(self.data_size, other.data_size) as u64);
+        for i in 0..data_bytes {
+            if self.get_data_byte(i) != other.get_data_byte(i) {
+                return Ok(false);
+            }
+        }
+
+        let pointer_count = ::std::cmp::max(self.pointer_count, other.pointer_count);
+        for i in 0..pointer_count as WirePointerCount {
+            if !try!(self.get_pointer_field(i).equals(&other.get_pointer_field(i))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
     pub fn get_pointer_field(&self, ptr_index: WirePointerCount) -> PointerReader<'a> {
         if ptr_index < self.pointer_count as WirePointerCount {
             PointerReader {
@@ -2318,6 +2930,47 @@ impl <'a> StructBuilder<'a> {
         }
     }
 
+    /// Replaces the contents of this struct with a deep copy of `other`. This struct keeps its
+    /// size: data and pointers that `other` does not have are zeroed, and those that do not fit
+    /// are dropped.
+    pub fn copy_content_from(&self, other: &StructReader) -> Result<()> {
+        let shared_data_size = ::std::cmp::min(self.data_size, other.data_size);
+        let shared_pointer_count = ::std::cmp::min(self.pointer_count, other.pointer_count);
+
+        if (shared_data_size > 0 && other.data == self.data as *const _) ||
+            (shared_pointer_count > 0 && other.pointers == self.pointers as *const _) {
+            // Copying from ourselves.
+            return Ok(());
+        }
+
+        unsafe {
+            if self.data_size == 1 {
+                self.set_bool_field(0, false);
+            } else {
+                ptr::write_bytes(self.data, 0, (self.data_size / BITS_PER_BYTE as u32) as usize);
+            }
+            if shared_data_size == 1 {
+                self.set_bool_field(0, other.get_bool_field(0));
+            } else {
+                ptr::copy_nonoverlapping(other.data, self.data,
+                                         (shared_data_size / BITS_PER_BYTE as u32) as usize);
+            }
+
+            for i in 0..self.pointer_count as isize {
+                let reff = self.pointers.offset(i);
+                if !(*reff).is_null() {
+                    wire_helpers::zero_object(self.segment, reff);
+                }
+                ptr::write_bytes(reff, 0, 1);
+            }
+            for i in 0..shared_pointer_count as isize {
+                try!(wire_helpers::copy_pointer(self.segment, self.pointers.offset(i),
+                                                other.segment, other.pointers.offset(i),
+                                                other.nesting_limit, false));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -2345,6 +2998,34 @@ impl <'a> ListReader<'a> {
     #[inline]
     pub fn len(&self) -> ElementCount32 { self.element_count }
 
+    /// Gets the elements as raw bytes, provided that each one takes up exactly `element_bytes`
+    /// bytes with no padding in between.
+    pub fn as_raw_bytes(&self, element_bytes: usize) -> Option<&'a [u8]> {
+        if self.step as usize != element_bytes * BITS_PER_BYTE {
+            None
+        } else if self.element_count == 0 {
+            Some(&[])
+        } else {
+            Some(unsafe {
+                ::std::slice::from_raw_parts(self.ptr, self.element_count as usize * element_bytes)
+            })
+        }
+    }
+
+    /// Gets the bytes of a list of bits, or `None` if the elements are not packed one bit apiece.
+    /// Bits past the end of the list in the last byte are not part of the list.
+    pub fn as_bit_bytes(&self) -> Option<&'a [u8]> {
+        if self.step != 1 {
+            None
+        } else if self.element_count == 0 {
+            Some(&[])
+        } else {
+            Some(unsafe {
+                ::std::slice::from_raw_parts(self.ptr, (self.element_count as usize + 7) / BITS_PER_BYTE)
+            })
+        }
+    }
+
     pub fn get_struct_element(&self, index: ElementCount32) -> StructReader<'a> {
         let index_bit: BitCount64 = index as ElementCount64 * (self.step as BitCount64);
 
@@ -2377,6 +3058,40 @@ impl <'a> ListReader<'a> {
             nesting_limit: self.nesting_limit
         }
     }
+
+    /// Compares two lists by value, viewing the elements of each as structs so that lists with
+    /// different element layouts can still be equal. See `PointerReader::equals()`.
+    pub fn equals(&self, other: &ListReader) -> Result<bool> {
+        if self.element_count != other.element_count {
+            return Ok(false);
+        }
+        if self.step == 1 || other.step == 1 {
+            //# Bit list elements do not start on byte boundaries, so compare them bit by bit.
+            if self.step != other.step {
+                return Ok(false);
+            }
+            for i in 0..self.element_count as isize {
+                let mask = 1u8 << (i % BITS_PER_BYTE as isize);
+                let byte_index = i / BITS_PER_BYTE as isize;
+                unsafe {
+                    if *self.ptr.offset(byte_index) & mask != *other.ptr.offset(byte_index) & mask {
+                        return Ok(false);
+                    }
+                }
+            }
+            return Ok(true);
+        }
+
+        if self.nesting_limit <= 0 || other.nesting_limit <= 0 {
+            return Err(Error::new_decode_error("Message is too deeply-nested or contains cycles.", None));
+        }
+        for i in 0..self.element_count {
+            if !try!(self.get_struct_element(i).equals(&other.get_struct_element(i))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -2404,6 +3119,32 @@ impl <'a> ListBuilder<'a> {
     #[inline]
     pub fn len(&self) -> ElementCount32 { self.element_count }
 
+    /// Gets the elements as raw bytes, provided that each one takes up exactly `element_bytes`
+    /// bytes with no padding in between.
+    pub fn as_raw_bytes_mut(&self, element_bytes: usize) -> Option<&'a mut [u8]> {
+        if self.step as usize != element_bytes * BITS_PER_BYTE {
+            None
+        } else if self.element_count == 0 {
+            Some(&mut [])
+        } else {
+            Some(unsafe {
+                ::std::slice::from_raw_parts_mut(self.ptr, self.element_count as usize * element_bytes)
+            })
+        }
+    }
+
+    pub fn as_bit_bytes_mut(&self) -> Option<&'a mut [u8]> {
+        if self.step != 1 {
+            None
+        } else if self.element_count == 0 {
+            Some(&mut [])
+        } else {
+            Some(unsafe {
+                ::std::slice::from_raw_parts_mut(self.ptr, (self.element_count as usize + 7) / BITS_PER_BYTE)
+            })
+        }
+    }
+
     pub fn get_struct_element(&self, index: ElementCount32) -> StructBuilder<'a> {
         let index_bit = index * self.step;
         let struct_data = unsafe{ self.ptr.offset((index_bit / BITS_PER_BYTE as u32) as isize)};
@@ -2434,6 +3175,12 @@ impl <'a> ListBuilder<'a> {
 
 
 pub trait PrimitiveElement: Endian {
+    /// The element size of a list of `Self`.
+    #[inline]
+    fn element_size() -> ElementSize {
+        element_size_for_type::<Self>()
+    }
+
     #[inline]
     fn get(list_reader: &ListReader, index: ElementCount32) -> Self {
         unsafe {
@@ -2477,6 +3224,10 @@ impl PrimitiveElement for f32 { }
 impl PrimitiveElement for f64 { }
 
 impl PrimitiveElement for bool {
+    // `element_size_for_type` would go by `size_of::<bool>()`, which is a byte.
+    #[inline]
+    fn element_size() -> ElementSize { Bit }
+
     #[inline]
     fn get(list: &ListReader, index: ElementCount32) -> bool {
         let bindex: BitCount0 = index as ElementCount * list.step as usize;
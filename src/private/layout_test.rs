@@ -61,3 +61,14 @@ fn simple_raw_data_struct() {
     assert_eq!(reader.get_bool_field(63), true);
     assert_eq!(reader.get_bool_field(64), false);
 }
+
+#[test]
+fn simple_raw_list_pointer_builder() {
+    // Whatever the host's byte order, the wire format is little-endian.
+    let mut message = ::message::Builder::new_default();
+    message.set_root("ab").unwrap();
+    let segments = message.get_segments_for_output();
+    assert_eq!(&[0x01, 0x00, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00,
+                 b'a', b'b', 0x00, 0x00, 0x00, 0x00, 0x00, 0x00][..],
+               ::Word::words_to_bytes(segments[0]));
+}
@@ -22,10 +22,13 @@
 //! Reading and writing of messages using the
 //! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream).
 
-use std::io::{Read, Write};
+use std::borrow::Cow;
+use std::cell::{RefCell, UnsafeCell};
+use std::io::{self, BufRead, IoSlice, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
 
 use message;
-use util::read_exact;
+use util::{crc32c, read_exact, read_words};
 use {Error, Result, Word};
 
 use byteorder::{ByteOrder, LittleEndian};
@@ -62,6 +65,136 @@ pub fn read_message_from_words<'a>(slice: &'a [Word],
     }
 }
 
+/// Segments read from a byte slice that may not be aligned. See
+/// `read_message_from_unaligned_bytes`.
+pub struct UnalignedSegments<'a> {
+    words: Cow<'a, [Word]>,
+    segment_slices : Vec<(usize, usize)>,
+}
+
+impl <'a> message::ReaderSegments for UnalignedSegments<'a> {
+    fn get_segment<'b>(&'b self, id: u32) -> Option<&'b [Word]> {
+        if id < self.segment_slices.len() as u32 {
+            let (a, b) = self.segment_slices[id as usize];
+            Some(&self.words[a..b])
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads a serialized message from a slice of bytes that need not be 8-byte aligned. If the
+/// slice happens to be aligned, the message is read in place, as with `read_message_from_words`;
+/// otherwise it is first copied into aligned space.
+pub fn read_message_from_unaligned_bytes<'a>(bytes: &'a [u8],
+                                             options: message::ReaderOptions)
+                                             -> Result<message::Reader<UnalignedSegments<'a>>> {
+    if bytes.len() % 8 != 0 {
+        return Err(Error::new_decode_error("Message length is not a multiple of the word size.",
+                                           Some(format!("{}", bytes.len()))));
+    }
+    let words: Cow<'a, [Word]> = if bytes.as_ptr() as usize % ::std::mem::align_of::<Word>() == 0 {
+        Cow::Borrowed(Word::bytes_to_words(bytes))
+    } else {
+        let mut words = Vec::with_capacity(bytes.len() / 8);
+        try!(read_words(&mut &bytes[..], &mut words, bytes.len() / 8));
+        Cow::Owned(words)
+    };
+
+    let (num_words, table_words, offsets) = {
+        let mut bytes = Word::words_to_bytes(&words[..]);
+        let (num_words, offsets) = try!(read_segment_table(&mut bytes, options));
+        (num_words, words.len() - bytes.len() / 8, offsets)
+    };
+    if num_words != words.len() - table_words {
+        return Err(Error::new_decode_error("Wrong number of words.",
+                                           Some(format!("Header claimed {} words, but message has {} words",
+                                                        num_words, words.len() - table_words))));
+    }
+    let segment_slices = offsets.iter().map(|&(a, b)| (a + table_words, b + table_words)).collect();
+    Ok(message::Reader::new(UnalignedSegments { words: words, segment_slices: segment_slices }, options))
+}
+
+/// Reads a message that was embedded in a `Data` field with `write_nested_message`. Data blobs
+/// start on a word boundary, so the nested message is normally read in place, borrowing the
+/// outer message's segment.
+pub fn read_nested_message<'a>(data: ::data::Reader<'a>,
+                               options: message::ReaderOptions)
+                               -> Result<message::Reader<UnalignedSegments<'a>>> {
+    read_message_from_unaligned_bytes(data, options)
+}
+
+/// Iterator over back-to-back serialized messages in a slice of words. See `message_iter`.
+pub struct MessageIter<'a> {
+    words: &'a [Word],
+    options: message::ReaderOptions,
+    failed: bool,
+}
+
+impl <'a> MessageIter<'a> {
+    /// Returns the words that have not yet been consumed. Once the iterator has returned `None`,
+    /// this is the incomplete message at the end of the slice, if any.
+    pub fn remainder(&self) -> &'a [Word] {
+        self.words
+    }
+
+    /// Returns whether `self.words` holds at least one complete segment table and the segments
+    /// it describes. Malformed tables are reported as complete so that `read_segment_table`
+    /// can produce the error.
+    fn has_complete_message(&self) -> bool {
+        let bytes = Word::words_to_bytes(self.words);
+        if bytes.len() < 8 {
+            return false;
+        }
+        let table_words = match segment_table_len(bytes) {
+            Some(table_words) => table_words,
+            None => return true,
+        };
+        if self.words.len() < table_words {
+            return false;
+        }
+        let segment_count = <LittleEndian as ByteOrder>::read_u32(&bytes[0..4]) as usize + 1;
+        let mut total_words = 0;
+        for idx in 0..segment_count {
+            let offset = 4 + idx * 4;
+            total_words += <LittleEndian as ByteOrder>::read_u32(&bytes[offset..offset + 4]) as usize;
+        }
+        self.words.len() - table_words >= total_words
+    }
+}
+
+impl <'a> Iterator for MessageIter<'a> {
+    type Item = Result<message::Reader<SliceSegments<'a>>>;
+
+    fn next(&mut self) -> Option<Result<message::Reader<SliceSegments<'a>>>> {
+        if self.failed || !self.has_complete_message() {
+            return None;
+        }
+        let mut bytes = Word::words_to_bytes(self.words);
+        let (total_words, segment_slices) = match read_segment_table(&mut bytes, self.options) {
+            Ok(table) => table,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        let rest = Word::bytes_to_words(bytes);
+        self.words = &rest[total_words..];
+        let segments = SliceSegments { words: &rest[..total_words], segment_slices: segment_slices };
+        Some(Ok(message::Reader::new(segments, self.options)))
+    }
+}
+
+/// Returns an iterator over the messages in `words`, which holds zero or more serialized
+/// messages back to back. Each message is read in place, without copying.
+///
+/// Iteration stops at the first message that is not completely contained in `words`; its
+/// words are available through `MessageIter::remainder`. If a segment table is invalid, an
+/// error is returned and iteration stops.
+pub fn message_iter<'a>(words: &'a [Word], options: message::ReaderOptions) -> MessageIter<'a> {
+    MessageIter { words: words, options: options, failed: false }
+}
+
 pub struct OwnedSegments {
     segment_slices : Vec<(usize, usize)>,
     owned_space : Vec<Word>,
@@ -78,6 +211,161 @@ impl ::message::ReaderSegments for OwnedSegments {
     }
 }
 
+impl OwnedSegments {
+    // Constructs segments from words and the `(start, end)` word offsets of each segment within
+    // them, as returned by `read_segment_table`. The offsets are not checked, so this is only
+    // for use within the crate.
+    #[doc(hidden)]
+    pub fn from_parts(owned_space: Vec<Word>, segment_slices: Vec<(usize, usize)>) -> OwnedSegments {
+        OwnedSegments { segment_slices: segment_slices, owned_space: owned_space }
+    }
+
+    /// Returns the underlying words, discarding the segment boundaries.
+    pub fn into_words(self) -> Vec<Word> {
+        self.owned_space
+    }
+
+    /// Returns the underlying words along with the `(start, end)` word offsets of each segment
+    /// within them.
+    pub fn into_parts(self) -> (Vec<Word>, Vec<(usize, usize)>) {
+        (self.owned_space, self.segment_slices)
+    }
+
+    /// Converts these segments into `SharedSegments`, which can be cloned cheaply and sent to
+    /// other threads.
+    pub fn into_shared(self) -> SharedSegments {
+        SharedSegments {
+            segment_slices: Arc::new(self.segment_slices),
+            owned_space: Arc::from(self.owned_space),
+        }
+    }
+}
+
+/// Segments whose space is reference counted, so that a single decoded message can be read
+/// from several threads at once. Clone the segments and construct a `message::Reader` from
+/// each clone; the words themselves are never copied.
+#[derive(Clone)]
+pub struct SharedSegments {
+    segment_slices : Arc<Vec<(usize, usize)>>,
+    owned_space : Arc<[Word]>,
+}
+
+impl ::message::ReaderSegments for SharedSegments {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [Word]> {
+        if id < self.segment_slices.len() as u32 {
+            let (a, b) = self.segment_slices[id as usize];
+            Some(&self.owned_space[a..b])
+        } else {
+            None
+        }
+    }
+}
+
+/// Segments borrowed from a memory-mapped file.
+#[cfg(feature="mmap")]
+pub struct MmapSegments {
+    mmap: ::memmap::Mmap,
+    segment_slices : Vec<(usize, usize)>,
+}
+
+#[cfg(feature="mmap")]
+impl ::message::ReaderSegments for MmapSegments {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [Word]> {
+        if id < self.segment_slices.len() as u32 {
+            let (a, b) = self.segment_slices[id as usize];
+            Some(&::Word::bytes_to_words(&self.mmap[..])[a..b])
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads a serialized message from a file by mapping it into memory. The segments are read
+/// directly out of the mapping, without copying.
+///
+/// The file must contain exactly one message. Modifying the file while the returned reader is
+/// alive results in undefined behavior.
+#[cfg(feature="mmap")]
+pub fn read_message_from_mmap(file: &::std::fs::File,
+                              options: message::ReaderOptions) -> Result<message::Reader<MmapSegments>> {
+    let mmap = try!(unsafe { ::memmap::Mmap::map(file) });
+    let (num_words, table_words, offsets) = {
+        let mut bytes = &mmap[..];
+        let (num_words, offsets) = try!(read_segment_table(&mut bytes, options));
+        (num_words, (mmap.len() - bytes.len()) / 8, offsets)
+    };
+    if mmap.len() % 8 != 0 || num_words != mmap.len() / 8 - table_words {
+        return Err(Error::new_decode_error("Wrong number of words.",
+                                           Some(format!("Header claimed {} words, but file has {} bytes",
+                                                        num_words, mmap.len()))));
+    }
+    let segment_slices = offsets.iter().map(|&(a, b)| (a + table_words, b + table_words)).collect();
+    Ok(message::Reader::new(MmapSegments { mmap: mmap, segment_slices: segment_slices }, options))
+}
+
+/// Segments that are read from a seekable stream the first time they are accessed.
+pub struct LazySegments<R> {
+    read: RefCell<R>,
+    segments_start: u64,
+    segment_slices: Vec<(usize, usize)>,
+    loaded: Vec<UnsafeCell<Option<Vec<Word>>>>,
+}
+
+impl <R> LazySegments<R> where R: Read + Seek {
+    fn load_segment(&self, id: usize) -> io::Result<()> {
+        let (a, b) = self.segment_slices[id];
+        let mut read = self.read.borrow_mut();
+        try!(read.seek(SeekFrom::Start(self.segments_start + a as u64 * 8)));
+        let mut words = Vec::with_capacity(b - a);
+        try!(read_words(&mut *read, &mut words, b - a));
+
+        // A segment is never replaced once loaded, so references handed out by `get_segment`
+        // stay valid for as long as `self`.
+        unsafe { *self.loaded[id].get() = Some(words); }
+        Ok(())
+    }
+}
+
+impl <R> message::ReaderSegments for LazySegments<R> where R: Read + Seek {
+    fn get_segment<'a>(&'a self, id: u32) -> Option<&'a [Word]> {
+        let id = id as usize;
+        if id >= self.segment_slices.len() {
+            return None;
+        }
+        unsafe {
+            if (*self.loaded[id].get()).is_none() && self.load_segment(id).is_err() {
+                return None;
+            }
+            (*self.loaded[id].get()).as_ref().map(|words| &words[..])
+        }
+    }
+}
+
+/// A message whose segments are loaded on demand. See `read_message_lazily`.
+pub type LazyMessageReader<R> = message::Reader<LazySegments<R>>;
+
+/// Reads a serialized message from a seekable stream, loading only the segment table and the
+/// first segment up front. The remaining segments are read from `read` the first time the
+/// message traverses into them, so inspecting a small part of a very large message does not
+/// require reading all of it.
+///
+/// If a segment fails to load after this function has returned, the pointer leading to it is
+/// reported as invalid.
+pub fn read_message_lazily<R>(mut read: R,
+                              options: message::ReaderOptions) -> Result<LazyMessageReader<R>>
+where R: Read + Seek {
+    let (_, segment_slices) = try!(read_segment_table(&mut read, options));
+    let segments_start = try!(read.seek(SeekFrom::Current(0)));
+    let segments = LazySegments {
+        read: RefCell::new(read),
+        segments_start: segments_start,
+        loaded: segment_slices.iter().map(|_| UnsafeCell::new(None)).collect(),
+        segment_slices: segment_slices,
+    };
+    try!(segments.load_segment(0));
+    Ok(message::Reader::new(segments, options))
+}
+
 /// Reads a serialized message from a stream with the provided options.
 ///
 /// For optimal performance, `read` should be a buffered reader type.
@@ -87,14 +375,166 @@ where R: Read {
     read_segments(read, total_words, segment_slices, options)
 }
 
+/// Reads past the next serialized message in `read` without keeping any of it. Only the
+/// segment table is parsed; the segments themselves are discarded as they are read.
+pub fn skip_message<R>(read: &mut R, options: message::ReaderOptions) -> Result<()>
+where R: Read {
+    let (total_words, _) = try!(read_segment_table(read, options));
+    let total_bytes = total_words as u64 * 8;
+    let skipped = try!(io::copy(&mut read.take(total_bytes), &mut io::sink()));
+    if skipped != total_bytes {
+        return Err(Error::from(io::Error::new(io::ErrorKind::Other, "Premature EOF")));
+    }
+    Ok(())
+}
+
+/// Reads a serialized message from a stream into `buffer`, which is reused for the message's
+/// segments. Any previous contents of `buffer` are discarded, but its capacity is kept, so
+/// reading many messages into the same buffer avoids repeatedly allocating space.
+pub fn read_message_into<'a, R>(read: &mut R,
+                                buffer: &'a mut Vec<Word>,
+                                options: message::ReaderOptions)
+                                -> Result<message::Reader<SliceSegments<'a>>>
+where R: Read {
+    let (total_words, segment_slices) = try!(read_segment_table(read, options));
+    buffer.clear();
+    try!(read_words(read, buffer, total_words));
+    let segments = SliceSegments { words: &buffer[..], segment_slices: segment_slices };
+    Ok(message::Reader::new(segments, options))
+}
+
+/// Reads a serialized message from a buffered stream with the provided options.
+///
+/// This behaves like `read_message`, but parses the segment table directly out of the
+/// reader's buffer when it is already there, and copies segments straight out of the buffer
+/// when they are fully contained in it, rather than issuing a read per table word.
+pub fn read_message_buffered<R>(read: &mut R,
+                                options: message::ReaderOptions)
+                                -> Result<message::Reader<OwnedSegments>>
+where R: BufRead {
+    let buffered_table = {
+        let buf = try!(read.fill_buf());
+        let table_bytes = if buf.len() >= 8 { segment_table_len(buf).map(|words| words * 8) } else { None };
+        match table_bytes {
+            Some(table_bytes) if buf.len() >= table_bytes => {
+                let mut table = &buf[..table_bytes];
+                Some((try!(read_segment_table(&mut table, options)), table_bytes))
+            }
+            _ => None,
+        }
+    };
+    let (total_words, segment_slices) = match buffered_table {
+        Some((table, table_bytes)) => {
+            read.consume(table_bytes);
+            table
+        }
+        None => try!(read_segment_table(read, options)),
+    };
+
+    let mut owned_space: Vec<Word> = Vec::with_capacity(total_words);
+    let body_bytes = total_words * 8;
+    let buffered_body = {
+        let buf = try!(read.fill_buf());
+        if buf.len() >= body_bytes {
+            try!(read_words(&mut &buf[..body_bytes], &mut owned_space, total_words));
+            true
+        } else {
+            false
+        }
+    };
+    if buffered_body {
+        read.consume(body_bytes);
+    } else {
+        try!(read_words(read, &mut owned_space, total_words));
+    }
+
+    let segments = OwnedSegments {segment_slices: segment_slices, owned_space: owned_space};
+    Ok(::message::Reader::new(segments, options))
+}
+
+/// Reads a message written by `write_message_checksummed`, verifying the checksum of its
+/// segments before returning it.
+pub fn read_message_checksummed<R>(read: &mut R,
+                                   options: message::ReaderOptions)
+                                   -> Result<message::Reader<OwnedSegments>>
+where R: Read {
+    let (total_words, segment_slices) = try!(read_segment_table(read, options));
+    let mut owned_space: Vec<Word> = Vec::with_capacity(total_words);
+    try!(read_words(read, &mut owned_space, total_words));
+
+    let mut buf: [u8; 8] = [0; 8];
+    try!(read_exact(read, &mut buf));
+    let expected = <LittleEndian as ByteOrder>::read_u32(&buf[0..4]);
+    let actual = crc32c(0, Word::words_to_bytes(&owned_space[..]));
+    if expected != actual {
+        return Err(Error::new_decode_error("Message checksum mismatch.",
+                                           Some(format!("expected {:08x}, computed {:08x}",
+                                                        expected, actual))));
+    }
+
+    let segments = OwnedSegments {segment_slices: segment_slices, owned_space: owned_space};
+    Ok(::message::Reader::new(segments, options))
+}
+
+/// Reads a message written by `write_message_length_prefixed`.
+///
+/// The frame length is checked against the traversal limit before the body is read, and the
+/// body is read into a buffer of exactly that size.
+pub fn read_message_length_prefixed<R>(read: &mut R,
+                                       options: message::ReaderOptions)
+                                       -> Result<message::Reader<OwnedSegments>>
+where R: Read {
+    let mut buf: [u8; 4] = [0; 4];
+    try!(read_exact(read, &mut buf));
+    let frame_bytes = <LittleEndian as ByteOrder>::read_u32(&buf) as usize;
+    if frame_bytes % 8 != 0 {
+        return Err(Error::new_decode_error("Frame length is not a multiple of the word size.",
+                                           Some(format!("{}", frame_bytes))));
+    }
+    let frame_words = frame_bytes / 8;
+    if frame_words as u64 > options.traversal_limit_in_words {
+        return Err(Error::new_decode_error(
+            "Message is too large. To increase the limit on the \
+             receiving end, see capnp::message::ReaderOptions.", Some(format!("{}", frame_words))));
+    }
+
+    let mut owned_space: Vec<Word> = Vec::with_capacity(frame_words);
+    try!(read_words(read, &mut owned_space, frame_words));
+    let (num_words, table_words, offsets) = {
+        let mut bytes = Word::words_to_bytes(&owned_space[..]);
+        let (num_words, offsets) = try!(read_segment_table(&mut bytes, options));
+        (num_words, frame_words - bytes.len() / 8, offsets)
+    };
+    if num_words != frame_words - table_words {
+        return Err(Error::new_decode_error("Wrong number of words.",
+                                           Some(format!("Header claimed {} words, but frame has {} words",
+                                                        num_words, frame_words - table_words))));
+    }
+    let segment_slices = offsets.iter().map(|&(a, b)| (a + table_words, b + table_words)).collect();
+    let segments = OwnedSegments {segment_slices: segment_slices, owned_space: owned_space};
+    Ok(::message::Reader::new(segments, options))
+}
+
+/// Given the first word of a segment table, returns the length of the whole table in words,
+/// or `None` if the segment count is invalid.
+fn segment_table_len(first_word: &[u8]) -> Option<usize> {
+    let segment_count = <LittleEndian as ByteOrder>::read_u32(&first_word[0..4]) as u64 + 1;
+    if segment_count >= 512 {
+        None
+    } else {
+        Some(segment_count as usize / 2 + 1)
+    }
+}
+
 /// Reads a segment table from `read` and returns the total number of words across all
 /// segments, as well as the segment offsets.
 ///
 /// The segment table format for streams is defined in the Cap'n Proto
 /// [encoding spec](https://capnproto.org/encoding.html)
-fn read_segment_table<R>(read: &mut R,
-                         options: message::ReaderOptions)
-                         -> Result<(usize, Vec<(usize, usize)>)>
+#[doc(hidden)]
+pub fn read_segment_table<R>(read: &mut R,
+                             options: message::ReaderOptions)
+                             -> Result<(usize, Vec<(usize, usize)>)>
 where R: Read {
 
     let mut buf: [u8; 8] = [0; 8];
@@ -158,8 +598,8 @@ fn read_segments<R>(read: &mut R,
                     options: message::ReaderOptions)
                     -> Result<message::Reader<OwnedSegments>>
 where R: Read {
-    let mut owned_space: Vec<Word> = Word::allocate_zeroed_vec(total_words);
-    try!(read_exact(read, Word::words_to_bytes_mut(&mut owned_space[..])));
+    let mut owned_space: Vec<Word> = Vec::with_capacity(total_words);
+    try!(read_words(read, &mut owned_space, total_words));
     let segments = OwnedSegments {segment_slices: segment_slices, owned_space: owned_space};
     Ok(::message::Reader::new(segments, options))
 }
@@ -192,12 +632,81 @@ fn flatten_segments(segments: &[&[Word]]) -> Vec<Word> {
 
 /// Writes the provided message to `write`.
 ///
-/// For optimal performance, `write` should be a buffered writer. `flush` will not be called on
-/// the writer.
+/// The segment table and the segments are handed to `write` together through
+/// `Write::write_vectored`, so a multi-segment message can go out in a single system call on
+/// writers that support vectored I/O. Other writers fall back to one write per segment.
+///
+/// `flush` will not be called on the writer.
 pub fn write_message<W, A>(write: &mut W, message: &message::Builder<A>) -> ::std::io::Result<()>
 where W: Write, A: message::Allocator {
     let segments = message.get_segments_for_output();
-    try!(write_segment_table(write, &*segments));
+    write_segments(write, &*segments)
+}
+
+/// Writes the provided message into the start of `buf`, returning the number of bytes written.
+///
+/// Returns an `InvalidInput` error, without writing anything, if `buf` is too small to hold
+/// the message.
+pub fn write_message_to_slice<A>(buf: &mut [u8], message: &message::Builder<A>) -> ::std::io::Result<usize>
+where A: message::Allocator {
+    let segments = message.get_segments_for_output();
+    let size = compute_serialized_size(&*segments) * 8;
+    if buf.len() < size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("message is {} bytes, but buffer is only {} bytes",
+                                          size, buf.len())));
+    }
+    try!(write_segments(&mut &mut buf[..size], &*segments));
+    Ok(size)
+}
+
+/// Serializes `message` into a `Data` field of another message, to be read back with
+/// `read_nested_message`. The field must have been initialized to exactly the serialized size,
+/// i.e. to `8 * compute_serialized_size_in_words(message)` bytes; otherwise an `InvalidInput`
+/// error is returned.
+pub fn write_nested_message<A>(data: ::data::Builder, message: &message::Builder<A>) -> ::std::io::Result<()>
+where A: message::Allocator {
+    let size = compute_serialized_size_in_words(message) * 8;
+    if data.len() != size {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("message is {} bytes, but data field is {} bytes",
+                                          size, data.len())));
+    }
+    try!(write_message_to_slice(data, message));
+    Ok(())
+}
+
+/// Writes the provided message to `write`, followed by a word holding the little-endian
+/// CRC-32C of the segment data in its first four bytes. Read the message back with
+/// `read_message_checksummed` to detect corruption in transit.
+///
+/// `flush` will not be called on the writer.
+pub fn write_message_checksummed<W, A>(write: &mut W, message: &message::Builder<A>) -> ::std::io::Result<()>
+where W: Write, A: message::Allocator {
+    let segments = message.get_segments_for_output();
+    try!(write_segments(write, &*segments));
+
+    let crc = segments.iter().fold(0, |crc, segment| crc32c(crc, Word::words_to_bytes(segment)));
+    let mut buf: [u8; 8] = [0; 8];
+    <LittleEndian as ByteOrder>::write_u32(&mut buf[0..4], crc);
+    write.write_all(&buf)
+}
+
+/// Writes the provided message to `write`, preceded by its total length in bytes as a
+/// little-endian `u32`. Read the message back with `read_message_length_prefixed`.
+///
+/// `flush` will not be called on the writer.
+pub fn write_message_length_prefixed<W, A>(write: &mut W, message: &message::Builder<A>) -> ::std::io::Result<()>
+where W: Write, A: message::Allocator {
+    let segments = message.get_segments_for_output();
+    let frame_bytes = compute_serialized_size(&*segments) * 8;
+    if frame_bytes > ::std::u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "message is too large for a length-prefixed frame"));
+    }
+    let mut buf: [u8; 4] = [0; 4];
+    <LittleEndian as ByteOrder>::write_u32(&mut buf, frame_bytes as u32);
+    try!(write.write_all(&buf));
     write_segments(write, &*segments)
 }
 
@@ -233,13 +742,67 @@ where W: Write {
     Ok(())
 }
 
-/// Writes segments to `write`.
+/// Writes the segment table followed by the segments to `write`.
+///
+/// `segments` must contain at least one segment.
 fn write_segments<W>(write: &mut W, segments: &[&[Word]]) -> ::std::io::Result<()>
 where W: Write {
+    let mut table = Vec::with_capacity((segments.len() / 2 + 1) * 8);
+    try!(write_segment_table(&mut table, segments));
+
+    let mut bufs = Vec::with_capacity(segments.len() + 1);
+    bufs.push(&table[..]);
     for segment in segments {
-        try!(write.write_all(Word::words_to_bytes(segment)));
+        bufs.push(Word::words_to_bytes(segment));
+    }
+    write_all_vectored(write, &bufs[..])
+}
+
+/// Writes every byte of `bufs` to `write`, retrying on interruption.
+fn write_all_vectored<W>(write: &mut W, mut bufs: &[&[u8]]) -> ::std::io::Result<()>
+where W: Write {
+    // Offset of the first unwritten byte in `bufs[0]`.
+    let mut offset = 0;
+    loop {
+        // Skip over any buffers that are empty or fully written.
+        while bufs.len() > 0 && offset == bufs[0].len() {
+            bufs = &bufs[1..];
+            offset = 0;
+        }
+        if bufs.len() == 0 {
+            return Ok(());
+        }
+
+        let mut slices = Vec::with_capacity(bufs.len());
+        slices.push(IoSlice::new(&bufs[0][offset..]));
+        for buf in &bufs[1..] {
+            slices.push(IoSlice::new(buf));
+        }
+        match write.write_vectored(&slices[..]) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "Failed to write whole message"));
+            }
+            Ok(mut n) => {
+                while n > 0 && bufs.len() > 0 {
+                    let remaining = bufs[0].len() - offset;
+                    if n < remaining {
+                        offset += n;
+                        n = 0;
+                    } else {
+                        n -= remaining;
+                        bufs = &bufs[1..];
+                        offset = 0;
+                    }
+                }
+            }
+            Err(e) => {
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+                // Retry if we were interrupted.
+            }
+        }
     }
-    Ok(())
 }
 
 fn compute_serialized_size(segments: &[&[Word]]) -> usize {
@@ -261,14 +824,20 @@ pub fn compute_serialized_size_in_words<A>(message: &::message::Builder<A>) -> u
 #[cfg(test)]
 pub mod test {
 
-    use std::io::{Cursor, Write};
+    use std::io::{BufReader, Cursor, Read, Write};
 
+    use byteorder::{ByteOrder, LittleEndian};
     use quickcheck::{quickcheck, TestResult};
 
     use {Word};
     use message;
     use message::ReaderSegments;
-    use super::{read_message, read_message_from_words, flatten_segments,
+    use util::crc32c;
+    use super::{read_message, read_message_buffered, read_message_from_words, read_message_into,
+                read_message_lazily, read_message_checksummed, write_message_checksummed,
+                read_message_length_prefixed, write_message_length_prefixed, write_message_to_slice,
+                read_nested_message, write_nested_message, compute_serialized_size_in_words,
+                message_iter, flatten_segments,
                 read_segment_table, write_segment_table, write_segments};
 
     /// Writes segments as if they were a Capnproto message.
@@ -276,7 +845,6 @@ pub mod test {
         let borrowed_segments: &[&[Word]] = &segments.iter()
                                                      .map(|segment| &segment[..])
                                                      .collect::<Vec<_>>()[..];
-        write_segment_table(write, borrowed_segments).unwrap();
         write_segments(write, borrowed_segments).unwrap();
     }
 
@@ -445,6 +1013,46 @@ pub mod test {
         quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
     }
 
+    /// A writer that accepts at most three bytes per call.
+    struct ShortWrite(Vec<u8>);
+
+    impl Write for ShortWrite {
+        fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize> {
+            let n = ::std::cmp::min(3, buf.len());
+            self.0.extend(buf[..n].iter().cloned());
+            Ok(n)
+        }
+        // Writes up to five bytes, possibly spanning several buffers.
+        fn write_vectored(&mut self, bufs: &[::std::io::IoSlice]) -> ::std::io::Result<usize> {
+            let mut n = 0;
+            for buf in bufs {
+                let take = ::std::cmp::min(5 - n, buf.len());
+                self.0.extend(buf[..take].iter().cloned());
+                n += take;
+            }
+            Ok(n)
+        }
+        fn flush(&mut self) -> ::std::io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn check_round_trip_short_writes() {
+        fn round_trip(segments: Vec<Vec<Word>>) -> TestResult {
+            if segments.len() == 0 { return TestResult::discard(); }
+            let mut write = ShortWrite(Vec::new());
+            write_message_segments(&mut write, &segments);
+
+            let message = read_message(&mut Cursor::new(write.0), message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+
+            TestResult::from_bool(segments.iter().enumerate().all(|(i, segment)| {
+                &segment[..] == result_segments.get_segment(i as u32).unwrap()
+            }))
+        }
+
+        quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
+    }
+
     #[test]
     fn check_round_trip_slice_segments() {
         fn round_trip(segments: Vec<Vec<Word>>) -> TestResult {
@@ -463,4 +1071,318 @@ pub mod test {
 
         quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
     }
+
+    #[test]
+    fn check_round_trip_buffered() {
+        fn round_trip(segments: Vec<Vec<Word>>, capacity: usize) -> TestResult {
+            if segments.len() == 0 { return TestResult::discard(); }
+            let mut cursor = Cursor::new(Vec::new());
+            write_message_segments(&mut cursor, &segments);
+            cursor.set_position(0);
+
+            let mut read = BufReader::with_capacity(capacity + 1, cursor);
+            let message = read_message_buffered(&mut read, message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+
+            TestResult::from_bool(segments.iter().enumerate().all(|(i, segment)| {
+                &segment[..] == result_segments.get_segment(i as u32).unwrap()
+            }))
+        }
+
+        quickcheck(round_trip as fn(Vec<Vec<Word>>, usize) -> TestResult);
+    }
+
+    #[test]
+    fn check_checksummed() {
+        let mut message = message::Builder::new_default();
+        message.set_root("checksummed").unwrap();
+        let mut bytes = Vec::new();
+        write_message_checksummed(&mut bytes, &message).unwrap();
+
+        {
+            let reader = read_message_checksummed(&mut &bytes[..], message::ReaderOptions::new()).unwrap();
+            assert_eq!("checksummed", reader.get_root::<&str>().unwrap());
+        }
+
+        bytes[20] ^= 1;
+        assert!(read_message_checksummed(&mut &bytes[..], message::ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn check_length_prefixed() {
+        let mut message = message::Builder::new(
+            message::HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        let mut bytes = Vec::new();
+        write_message_length_prefixed(&mut bytes, &message).unwrap();
+        assert_eq!(bytes.len() - 4, LittleEndian::read_u32(&bytes[0..4]) as usize);
+
+        {
+            let reader = read_message_length_prefixed(&mut &bytes[..],
+                                                      message::ReaderOptions::new()).unwrap();
+            assert_eq!("this text does not fit in the first segment",
+                       reader.get_root::<&str>().unwrap());
+        }
+
+        let mut options = message::ReaderOptions::new();
+        options.traversal_limit_in_words(1);
+        assert!(read_message_length_prefixed(&mut &bytes[..], options).is_err());
+    }
+
+    #[test]
+    fn check_write_message_to_slice() {
+        let mut message = message::Builder::new_default();
+        message.set_root("in a slice").unwrap();
+        let mut buf = [0u8; 64];
+        let size = write_message_to_slice(&mut buf, &message).unwrap();
+        assert_eq!(32, size);
+        {
+            let reader = read_message(&mut &buf[..size], message::ReaderOptions::new()).unwrap();
+            assert_eq!("in a slice", reader.get_root::<&str>().unwrap());
+        }
+
+        assert!(write_message_to_slice(&mut buf[..31], &message).is_err());
+    }
+
+    #[test]
+    fn check_nested_message() {
+        use std::borrow::Cow;
+        use any_pointer;
+
+        let mut inner = message::Builder::new_default();
+        inner.set_root("nested").unwrap();
+
+        let mut outer = message::Builder::new_default();
+        {
+            let root: any_pointer::Builder = outer.init_root();
+            assert!(write_nested_message(root.initn_as(8), &inner).is_err());
+        }
+        {
+            let size = compute_serialized_size_in_words(&inner) as u32 * 8;
+            let root: any_pointer::Builder = outer.init_root();
+            write_nested_message(root.initn_as(size), &inner).unwrap();
+        }
+
+        let data = outer.get_root_as_reader::<::data::Reader>().unwrap();
+        let reader = read_nested_message(data, message::ReaderOptions::new()).unwrap();
+        assert_eq!("nested", reader.get_root::<&str>().unwrap());
+        match reader.into_segments().words {
+            Cow::Borrowed(words) => assert_eq!(data.as_ptr(), words.as_ptr() as *const u8),
+            Cow::Owned(_) => panic!("nested message was copied"),
+        }
+    }
+
+    #[test]
+    fn check_crc32c() {
+        assert_eq!(0xe3069283, crc32c(0, b"123456789"));
+        assert_eq!(0xe3069283, crc32c(crc32c(0, b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn check_read_message_from_unaligned_bytes() {
+        use super::read_message_from_unaligned_bytes;
+
+        let segments = vec![vec![Word::from(1); 3], vec![Word::from(2); 2]];
+        let borrowed_segments: &[&[Word]] = &segments.iter()
+                                                     .map(|segment| &segment[..])
+                                                     .collect::<Vec<_>>()[..];
+        let aligned = flatten_segments(borrowed_segments);
+        let mut shifted = vec![0u8];
+        shifted.extend(Word::words_to_bytes(&aligned[..]).iter().cloned());
+
+        for bytes in &[Word::words_to_bytes(&aligned[..]), &shifted[1..]] {
+            let message = read_message_from_unaligned_bytes(bytes, message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+            for (i, segment) in segments.iter().enumerate() {
+                assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn check_owned_segments_into_parts() {
+        let segments = vec![vec![Word::from(1); 3], vec![Word::from(2); 2]];
+        let mut bytes = Vec::new();
+        write_message_segments(&mut bytes, &segments);
+        let (words, segment_slices) = read_message(&mut &bytes[..], message::ReaderOptions::new())
+            .unwrap().into_segments().into_parts();
+        assert_eq!(vec![(0, 3), (3, 5)], segment_slices);
+        assert_eq!(&segments[1][..], &words[3..5]);
+    }
+
+    #[test]
+    fn check_skip_message() {
+        use super::skip_message;
+
+        let mut cursor = Cursor::new(Vec::new());
+        write_message_segments(&mut cursor, &vec![vec![Word::from(1); 3], vec![Word::from(2); 2]]);
+        write_message_segments(&mut cursor, &vec![vec![Word::from(3); 1]]);
+        cursor.set_position(0);
+
+        skip_message(&mut cursor, message::ReaderOptions::new()).unwrap();
+        let result_segments = read_message(&mut cursor, message::ReaderOptions::new()).unwrap()
+            .into_segments();
+        assert_eq!(&[Word::from(3)], result_segments.get_segment(0).unwrap());
+        assert!(skip_message(&mut cursor, message::ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn check_shared_segments() {
+        let mut message = message::Builder::new_default();
+        message.set_root("shared").unwrap();
+        let mut bytes = Vec::new();
+        super::write_message(&mut bytes, &message).unwrap();
+        let shared = read_message(&mut &bytes[..], message::ReaderOptions::new()).unwrap()
+            .into_segments().into_shared();
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let segments = shared.clone();
+            ::std::thread::spawn(move || {
+                let reader = message::Reader::new(segments, message::ReaderOptions::new());
+                assert_eq!("shared", reader.get_root::<&str>().unwrap());
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn check_message_iter() {
+        let first = vec![vec![Word::from(1); 3], vec![Word::from(2); 2]];
+        let second = vec![vec![Word::from(3); 1]];
+        let mut cursor = Cursor::new(Vec::new());
+        write_message_segments(&mut cursor, &first);
+        write_message_segments(&mut cursor, &second);
+        let mut words = Word::bytes_to_words(&cursor.into_inner()[..]).to_vec();
+        words.truncate(words.len() - 1);
+
+        let mut iter = message_iter(&words[..], message::ReaderOptions::new());
+        let result_segments = iter.next().unwrap().unwrap().into_segments();
+        for (i, segment) in first.iter().enumerate() {
+            assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+        }
+        assert!(iter.next().is_none());
+        assert_eq!(1, iter.remainder().len());
+    }
+
+    #[test]
+    fn check_read_message_into() {
+        let first = vec![vec![Word::from(1); 7], vec![Word::from(2); 2]];
+        let second = vec![vec![Word::from(3); 1]];
+        let mut cursor = Cursor::new(Vec::new());
+        write_message_segments(&mut cursor, &first);
+        write_message_segments(&mut cursor, &second);
+        cursor.set_position(0);
+
+        let mut buffer = Vec::new();
+        for segments in &[first, second] {
+            let message = read_message_into(&mut cursor, &mut buffer,
+                                            message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+            for (i, segment) in segments.iter().enumerate() {
+                assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+            }
+            assert!(result_segments.get_segment(segments.len() as u32).is_none());
+        }
+    }
+
+    #[test]
+    fn check_round_trip_lazy() {
+        fn round_trip(segments: Vec<Vec<Word>>) -> TestResult {
+            if segments.len() == 0 { return TestResult::discard(); }
+            let mut cursor = Cursor::new(Vec::new());
+            write_message_segments(&mut cursor, &segments);
+            cursor.set_position(0);
+
+            let message = read_message_lazily(cursor, message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+
+            TestResult::from_bool(segments.iter().enumerate().rev().all(|(i, segment)| {
+                &segment[..] == result_segments.get_segment(i as u32).unwrap()
+            }))
+        }
+
+        quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
+    }
+
+    /// A reader that produces at most three bytes per call.
+    struct ShortRead(Cursor<Vec<u8>>);
+
+    impl Read for ShortRead {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = ::std::cmp::min(3, buf.len());
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    #[test]
+    fn check_round_trip_short_reads() {
+        fn round_trip(segments: Vec<Vec<Word>>) -> TestResult {
+            if segments.len() == 0 { return TestResult::discard(); }
+            let mut cursor = Cursor::new(Vec::new());
+            write_message_segments(&mut cursor, &segments);
+            cursor.set_position(0);
+
+            let message = read_message(&mut ShortRead(cursor), message::ReaderOptions::new()).unwrap();
+            let result_segments = message.into_segments();
+
+            TestResult::from_bool(segments.iter().enumerate().all(|(i, segment)| {
+                &segment[..] == result_segments.get_segment(i as u32).unwrap()
+            }))
+        }
+
+        quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
+    }
+
+    #[test]
+    fn read_message_premature_eof() {
+        let mut cursor = Cursor::new(Vec::new());
+        write_message_segments(&mut cursor, &vec![vec![Word::from(1); 4]]);
+        let mut bytes = cursor.into_inner();
+        bytes.truncate(20);
+        assert!(read_message(&mut Cursor::new(bytes), message::ReaderOptions::new()).is_err());
+    }
+
+    /// A reader that reports having read more bytes than the buffer it was given.
+    struct OverclaimingRead<R>(R);
+
+    impl <R: Read> Read for OverclaimingRead<R> {
+        fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+            let n = try!(self.0.read(buf));
+            Ok(if n == 0 { 0 } else { n + 64 })
+        }
+    }
+
+    #[test]
+    fn read_message_overclaiming_reader() {
+        let segments = vec![vec![Word::from(1); 4], vec![Word::from(2); 3]];
+        let mut cursor = Cursor::new(Vec::new());
+        write_message_segments(&mut cursor, &segments);
+        let mut read = OverclaimingRead(Cursor::new(cursor.into_inner()));
+
+        let message = read_message(&mut read, message::ReaderOptions::new()).unwrap();
+        let result_segments = message.into_segments();
+        assert_eq!(&segments[0][..], result_segments.get_segment(0).unwrap());
+        assert_eq!(&segments[1][..], result_segments.get_segment(1).unwrap());
+    }
+
+    #[cfg(feature="mmap")]
+    #[test]
+    fn check_read_message_from_mmap() {
+        use std::fs::{self, File};
+        use super::read_message_from_mmap;
+
+        let segments = vec![vec![Word::from(1); 3], vec![Word::from(2); 0], vec![Word::from(3); 5]];
+        let path = ::std::env::temp_dir().join("capnp-check-read-message-from-mmap");
+        write_message_segments(&mut File::create(&path).unwrap(), &segments);
+
+        let message = read_message_from_mmap(&File::open(&path).unwrap(),
+                                             message::ReaderOptions::new()).unwrap();
+        let result_segments = message.into_segments();
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+        }
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,186 @@
+// Copyright (c) 2013-2015 Sandstorm Development Group, Inc. and contributors
+// Licensed under the MIT License:
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN
+// THE SOFTWARE.
+
+//! Reading and writing of messages in the
+//! [standard stream framing](https://capnproto.org/encoding.html#serialization-over-a-stream),
+//! compressed with lz4 (the `lz4` feature) or zstd (the `zstd` feature).
+//!
+//! Each compressed message is preceded by a word holding the little-endian `u32` sizes in bytes
+//! of the uncompressed and compressed data, in that order. The reader checks both sizes
+//! against the traversal limit before allocating anything.
+
+use std::io::{self, Read, Write};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use message;
+use serialize;
+use util::read_exact;
+use {Error, Result};
+
+/// A compression algorithm.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    #[cfg(feature="lz4")]
+    Lz4,
+    #[cfg(feature="zstd")]
+    Zstd,
+}
+
+/// Level used for zstd compression.
+#[cfg(feature="zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Upper bound on the compressed size of `uncompressed_bytes` bytes, for any supported
+/// algorithm. Used to reject frames with implausible headers.
+fn max_compressed_size(uncompressed_bytes: usize) -> usize {
+    uncompressed_bytes + uncompressed_bytes / 128 + 64
+}
+
+fn compress(compression: Compression, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    match compression {
+        #[cfg(feature="lz4")]
+        Compression::Lz4 => ::lz4::block::compress(bytes, None, false),
+        #[cfg(feature="zstd")]
+        Compression::Zstd => ::zstd::block::compress(bytes, ZSTD_LEVEL),
+    }
+}
+
+fn decompress(compression: Compression, bytes: &[u8], uncompressed_bytes: usize) -> io::Result<Vec<u8>> {
+    match compression {
+        #[cfg(feature="lz4")]
+        Compression::Lz4 => {
+            // The lz4 bindings take the size as an `i32`.
+            if uncompressed_bytes > ::std::i32::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          "lz4 frame is too large to decompress"));
+            }
+            ::lz4::block::decompress(bytes, Some(uncompressed_bytes as i32))
+        }
+        #[cfg(feature="zstd")]
+        Compression::Zstd => ::zstd::block::decompress(bytes, uncompressed_bytes),
+    }
+}
+
+/// Writes the provided message to `write`, compressed with `compression`.
+///
+/// `flush` will not be called on the writer.
+pub fn write_message<W, A>(write: &mut W,
+                           message: &message::Builder<A>,
+                           compression: Compression) -> io::Result<()>
+where W: Write, A: message::Allocator {
+    let words = serialize::write_message_to_words(message);
+    let uncompressed = ::Word::words_to_bytes(&words[..]);
+    let compressed = try!(compress(compression, uncompressed));
+    if uncompressed.len() > ::std::u32::MAX as usize || compressed.len() > ::std::u32::MAX as usize {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  "message is too large for a compressed frame"));
+    }
+
+    let mut buf: [u8; 8] = [0; 8];
+    <LittleEndian as ByteOrder>::write_u32(&mut buf[0..4], uncompressed.len() as u32);
+    <LittleEndian as ByteOrder>::write_u32(&mut buf[4..8], compressed.len() as u32);
+    try!(write.write_all(&buf));
+    write.write_all(&compressed[..])
+}
+
+/// Reads a message written by `write_message` with the same `compression`.
+pub fn read_message<R>(read: &mut R,
+                       compression: Compression,
+                       options: message::ReaderOptions)
+                       -> Result<message::Reader<serialize::OwnedSegments>>
+where R: Read {
+    let mut buf: [u8; 8] = [0; 8];
+    try!(read_exact(read, &mut buf));
+    let uncompressed_bytes = <LittleEndian as ByteOrder>::read_u32(&buf[0..4]) as usize;
+    let compressed_bytes = <LittleEndian as ByteOrder>::read_u32(&buf[4..8]) as usize;
+
+    if uncompressed_bytes as u64 > options.traversal_limit_in_words.saturating_mul(8) {
+        return Err(Error::new_decode_error(
+            "Message is too large. To increase the limit on the \
+             receiving end, see capnp::message::ReaderOptions.",
+            Some(format!("{}", uncompressed_bytes / 8))));
+    }
+    if compressed_bytes > max_compressed_size(uncompressed_bytes) {
+        return Err(Error::new_decode_error("Compressed size is implausibly large.",
+                                           Some(format!("{} bytes compressed, {} uncompressed",
+                                                        compressed_bytes, uncompressed_bytes))));
+    }
+
+    let mut compressed = vec![0u8; compressed_bytes];
+    try!(read_exact(read, &mut compressed[..]));
+    let uncompressed = try!(decompress(compression, &compressed[..], uncompressed_bytes));
+    if uncompressed.len() != uncompressed_bytes {
+        return Err(Error::new_decode_error("Wrong uncompressed size.",
+                                           Some(format!("Header claimed {} bytes, but got {} bytes",
+                                                        uncompressed_bytes, uncompressed.len()))));
+    }
+
+    let mut remaining = &uncompressed[..];
+    let message = try!(serialize::read_message(&mut remaining, options));
+    if remaining.len() != 0 {
+        return Err(Error::new_decode_error("Trailing bytes after compressed message.",
+                                           Some(format!("{}", remaining.len()))));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use message;
+    use super::{read_message, write_message, Compression};
+
+    fn check_round_trip(compression: Compression) {
+        let mut message = message::Builder::new_default();
+        message.set_root("compressed compressed compressed compressed").unwrap();
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &message, compression).unwrap();
+
+        let reader = read_message(&mut &bytes[..], compression, message::ReaderOptions::new()).unwrap();
+        assert_eq!("compressed compressed compressed compressed", reader.get_root::<&str>().unwrap());
+
+        let mut options = message::ReaderOptions::new();
+        options.traversal_limit_in_words(1);
+        assert!(read_message(&mut &bytes[..], compression, options).is_err());
+    }
+
+    #[cfg(feature="lz4")]
+    #[test]
+    fn round_trip_lz4() {
+        check_round_trip(Compression::Lz4);
+    }
+
+    #[cfg(feature="lz4")]
+    #[test]
+    fn lz4_frame_over_2_gib() {
+        // Claims 3 GiB uncompressed from no compressed bytes at all.
+        let header = [0, 0, 0, 0xc0, 0, 0, 0, 0];
+        let mut options = message::ReaderOptions::new();
+        options.traversal_limit_in_words(::std::u64::MAX);
+        assert!(read_message(&mut &header[..], Compression::Lz4, options).is_err());
+    }
+
+    #[cfg(feature="zstd")]
+    #[test]
+    fn round_trip_zstd() {
+        check_round_trip(Compression::Zstd);
+    }
+}
@@ -25,210 +25,468 @@
 use std::{io, mem, ptr, slice};
 use std::io::{Read, BufRead, Write};
 
+use byteorder::{ByteOrder, LittleEndian};
+
 use serialize;
-use Result;
+use {Error, Result, Word};
 use message::*;
-use util::read_exact;
 
-struct PackedRead<R> where R: BufRead {
+/// A `Read` adaptor that unpacks data read from `inner`, which must hold data packed by
+/// `PackedWrite`. This makes it possible to use the functions in `serialize` on packed
+/// streams, for example `serialize::read_message(&mut PackedRead::new(read), options)`.
+///
+/// `inner` must be buffered; wrap unbuffered readers in a `std::io::BufReader`. Reads of any
+/// length are supported. If `inner` ends partway through a packed word or run, `read` returns
+/// an `UnexpectedEof` error.
+pub struct PackedRead<R> where R: BufRead {
     inner: R,
+
+    //# The most recently unpacked word, and how much of it has been returned so far.
+    word: [u8; 8],
+    word_pos: usize,
+
+    //# Bytes left to return from the run of zero words or of literal words that follows the
+    //# current word.
+    zero_run: usize,
+    literal_run: usize,
+
+    //# Number of unpacked bytes that the input has described so far, and the most it may.
+    unpacked: u64,
+    limit: u64,
 }
 
 impl <R> PackedRead<R> where R: BufRead {
+    pub fn new(inner: R) -> PackedRead<R> {
+        PackedRead::with_limit(inner, u64::max_value())
+    }
 
-    fn get_read_buffer(&mut self) -> io::Result<(*const u8, *const u8)> {
-        let buf = try!(self.inner.fill_buf());
-        unsafe {
-            Ok((buf.as_ptr(), buf.get_unchecked(buf.len())))
+    /// Like `new`, but fails with an `InvalidData` error as soon as the packed input describes
+    /// more than `limit` unpacked bytes in total. The limit is checked when each word and the
+    /// run that follows it are decoded, before any of the run is produced, so a short input
+    /// cannot make this reader emit more than `limit` bytes.
+    pub fn with_limit(inner: R, limit: u64) -> PackedRead<R> {
+        PackedRead {
+            inner: inner,
+            word: [0; 8],
+            word_pos: 8,
+            zero_run: 0,
+            literal_run: 0,
+            unpacked: 0,
+            limit: limit,
+        }
+    }
+
+    /// Returns the underlying reader. It is positioned just after the packed data that has
+    /// been unpacked so far.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns true if everything unpacked so far has been returned by `read`, so that the
+    /// underlying reader is positioned between packed words.
+    fn is_between_words(&self) -> bool {
+        self.word_pos == 8 && self.zero_run == 0 && self.literal_run == 0
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = match try!(self.inner.fill_buf()).first() {
+            Some(&byte) => byte,
+            None => return Ok(None),
+        };
+        self.inner.consume(1);
+        Ok(Some(byte))
+    }
+
+    fn read_byte_or_eof(&mut self) -> io::Result<u8> {
+        match try!(self.read_byte()) {
+            Some(byte) => Ok(byte),
+            None => Err(premature_eof()),
+        }
+    }
+
+    /// Unpacks the next word into `self.word`, and notes the length of the run that follows it,
+    /// if any. Returns false if the input ends cleanly before the word.
+    fn unpack_word(&mut self) -> io::Result<bool> {
+        let tag = match try!(self.read_byte()) {
+            Some(tag) => tag,
+            None => return Ok(false),
+        };
+        let has_run = tag == 0 || tag == 0xff;
+        let needed = tag.count_ones() as usize + has_run as usize;
+
+        let run = if try!(self.inner.fill_buf()).len() >= needed {
+            //# Fast path: the rest of the word and its run length are already buffered.
+            let run = {
+                let buf = try!(self.inner.fill_buf());
+                let mut pos = 0;
+                for n in 0..8 {
+                    self.word[n] = if (tag & (1u8 << n)) != 0 { pos += 1; buf[pos - 1] } else { 0 };
+                }
+                if has_run { buf[pos] } else { 0 }
+            };
+            self.inner.consume(needed);
+            run
+        } else {
+            for n in 0..8 {
+                self.word[n] = if (tag & (1u8 << n)) != 0 { try!(self.read_byte_or_eof()) } else { 0 };
+            }
+            if has_run { try!(self.read_byte_or_eof()) } else { 0 }
+        };
+
+        let described = 8 + run as u64 * 8;
+        if described > self.limit - self.unpacked {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "Packed input exceeds the decompressed size limit"));
         }
+        self.unpacked += described;
+
+        self.word_pos = 0;
+        if tag == 0 {
+            self.zero_run = run as usize * 8;
+        } else if tag == 0xff {
+            self.literal_run = run as usize * 8;
+        }
+        Ok(true)
     }
 }
 
+fn premature_eof() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "Premature end of packed input")
+}
+
+/// Upper bound on the size of a segment table that `read_segment_table` accepts, which is fewer
+/// than 512 segments.
+const MAX_SEGMENT_TABLE_WORDS: u64 = 512 / 2 + 1;
+
 #[inline]
 fn ptr_sub<T>(p1: *const T, p2: *const T) -> usize {
     return (p1 as usize - p2 as usize) / mem::size_of::<T>();
 }
 
-macro_rules! refresh_buffer(
-    ($this:expr, $size:ident, $in_ptr:ident, $in_end:ident, $out:ident,
-     $outBuf:ident, $buffer_begin:ident) => (
-        {
-            $this.inner.consume($size);
-            let (b, e) = try!($this.get_read_buffer());
-            $in_ptr = b;
-            $in_end = e;
-            $size = ptr_sub($in_end, $in_ptr);
-            $buffer_begin = b;
-            assert!($size > 0);
-        }
-        );
-    );
-
 impl <R> Read for PackedRead<R> where R: BufRead {
-
     fn read(&mut self, out_buf: &mut [u8]) -> io::Result<usize> {
-        let len = out_buf.len();
+        let mut out = 0;
+        while out < out_buf.len() {
+            let remaining = out_buf.len() - out;
+            if self.word_pos < 8 {
+                let n = ::std::cmp::min(8 - self.word_pos, remaining);
+                out_buf[out..out + n].copy_from_slice(&self.word[self.word_pos..self.word_pos + n]);
+                self.word_pos += n;
+                out += n;
+            } else if self.zero_run > 0 {
+                let n = ::std::cmp::min(self.zero_run, remaining);
+                for byte in &mut out_buf[out..out + n] {
+                    *byte = 0;
+                }
+                self.zero_run -= n;
+                out += n;
+            } else if self.literal_run > 0 {
+                let n = {
+                    let buf = try!(self.inner.fill_buf());
+                    if buf.is_empty() {
+                        return Err(premature_eof());
+                    }
+                    let n = ::std::cmp::min(::std::cmp::min(self.literal_run, remaining), buf.len());
+                    out_buf[out..out + n].copy_from_slice(&buf[..n]);
+                    n
+                };
+                self.inner.consume(n);
+                self.literal_run -= n;
+                out += n;
+            } else if !try!(self.unpack_word()) {
+                break;
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Reads a packed message from a stream using the provided options.
+///
+/// The unpacked size claimed by the segment table is checked against
+/// `options.traversal_limit_in_words` before any space is allocated for the segments. The
+/// unpacker itself is also capped at that many words, plus room for the largest possible
+/// segment table, so a small packed input cannot expand to more than the traversal limit
+/// allows; lower the limit to cap the decompressed size further.
+pub fn read_message<R>(read: &mut R,
+                       options: ReaderOptions)
+                       -> Result<::message::Reader<serialize::OwnedSegments>>
+    where R: BufRead
+{
+    let limit_words = options.traversal_limit_in_words.saturating_add(MAX_SEGMENT_TABLE_WORDS);
+    let mut packed_read = PackedRead::with_limit(read, limit_words.saturating_mul(8));
+    let message = try!(serialize::read_message(&mut packed_read, options));
+    if !packed_read.is_between_words() {
+        return Err(Error::new_decode_error(
+            "Packed input did not end cleanly on a segment boundary.", None));
+    }
+    Ok(message)
+}
 
-        if len == 0 { return Ok(0); }
+/// Unpacks exactly `out.len()` bytes from the front of `input`, advancing `input` past the
+/// packed bytes that were consumed. `out.len()` must be a multiple of eight.
+fn unpack_words_from_slice(input: &mut &[u8], out: &mut [u8]) -> Result<()> {
+    let packed = *input;
+    let mut in_pos = 0;
+    let mut out_pos = 0;
+    let premature_end = || Error::new_decode_error("Premature end of packed input.", None);
+
+    while out_pos < out.len() {
+        if in_pos == packed.len() { return Err(premature_end()); }
+        let tag = packed[in_pos];
+        in_pos += 1;
+
+        for n in 0..8 {
+            if (tag & (1u8 << n)) != 0 {
+                if in_pos == packed.len() { return Err(premature_end()); }
+                out[out_pos] = packed[in_pos];
+                in_pos += 1;
+            } else {
+                out[out_pos] = 0;
+            }
+            out_pos += 1;
+        }
 
-        assert!(len % 8 == 0, "PackedRead reads must be word-aligned");
+        if tag == 0 || tag == 0xff {
+            if in_pos == packed.len() { return Err(premature_end()); }
+            let run_length = packed[in_pos] as usize * 8;
+            in_pos += 1;
 
-        unsafe {
-            let mut out = out_buf.as_mut_ptr();
-            let out_end: *mut u8 = out_buf.get_unchecked_mut(len);
-
-            let (mut in_ptr, mut in_end) = try!(self.get_read_buffer());
-            let mut buffer_begin = in_ptr;
-            let mut size = ptr_sub(in_end, in_ptr);
-            if size == 0 {
-                return Ok(0);
+            if run_length > out.len() - out_pos {
+                return Err(Error::new_decode_error(
+                    "Packed input did not end cleanly on a segment boundary.", None));
             }
 
-            loop {
+            if tag == 0 {
+                for byte in &mut out[out_pos..out_pos + run_length] {
+                    *byte = 0;
+                }
+            } else {
+                if run_length > packed.len() - in_pos { return Err(premature_end()); }
+                out[out_pos..out_pos + run_length].copy_from_slice(&packed[in_pos..in_pos + run_length]);
+                in_pos += run_length;
+            }
+            out_pos += run_length;
+        }
+    }
 
-                let tag : u8;
+    *input = &packed[in_pos..];
+    Ok(())
+}
 
-                assert!(ptr_sub(out, out_buf.as_mut_ptr()) % 8 == 0,
-                        "Output pointer should always be aligned here.");
+/// Unpacks the segment table and segments of the message at the front of `input`, advancing
+/// `input` past it. Returns the unpacked table, the segment offsets, and the segments.
+fn unpack_message_from_slice(input: &mut &[u8],
+                             options: ReaderOptions)
+                             -> Result<(Vec<Word>, Vec<(usize, usize)>, Vec<Word>)>
+{
+    let mut table: Vec<Word> = Word::allocate_zeroed_vec(1);
+    try!(unpack_words_from_slice(input, Word::words_to_bytes_mut(&mut table[..])));
+    let segment_count =
+        <LittleEndian as ByteOrder>::read_u32(Word::words_to_bytes(&table[..])) as usize + 1;
+    if segment_count < 512 && segment_count > 1 {
+        let table_words = segment_count / 2 + 1;
+        table.extend(::std::iter::repeat(Word(0)).take(table_words - 1));
+        try!(unpack_words_from_slice(input, Word::words_to_bytes_mut(&mut table[1..])));
+    }
+    let (total_words, segment_slices) =
+        try!(serialize::read_segment_table(&mut Word::words_to_bytes(&table[..]), options));
 
-                if ptr_sub(in_end, in_ptr) < 10 {
-                    if out >= out_end {
-                        self.inner.consume(ptr_sub(in_ptr, buffer_begin));
-                        return Ok(ptr_sub(out, out_buf.as_mut_ptr()));
-                    }
+    let mut owned_space = Word::allocate_zeroed_vec(total_words);
+    try!(unpack_words_from_slice(input, Word::words_to_bytes_mut(&mut owned_space[..])));
+    Ok((table, segment_slices, owned_space))
+}
 
-                    if ptr_sub(in_end, in_ptr) == 0 {
-                        refresh_buffer!(self, size, in_ptr, in_end, out, out_buf, buffer_begin);
-                        continue;
-                    }
+/// Reads a packed message from the front of `packed`, returning the message along with the
+/// number of bytes of `packed` that it occupied. Any bytes after the message are left
+/// untouched, so concatenated packed messages can be read by calling this repeatedly on the
+/// remaining input.
+pub fn read_message_from_slice(packed: &[u8],
+                               options: ReaderOptions)
+                               -> Result<(::message::Reader<serialize::OwnedSegments>, usize)>
+{
+    let mut input = packed;
+    let (_, segment_slices, owned_space) = try!(unpack_message_from_slice(&mut input, options));
+    let segments = serialize::OwnedSegments::from_parts(owned_space, segment_slices);
+    Ok((::message::Reader::new(segments, options), packed.len() - input.len()))
+}
 
-                    //# We have at least 1, but not 10, bytes available. We need to read
-                    //# slowly, doing a bounds check on each byte.
-
-                    tag = *in_ptr;
-                    in_ptr = in_ptr.offset(1);
-
-                    for i in 0..8 {
-                        if (tag & (1u8 << i)) != 0 {
-                            if ptr_sub(in_end, in_ptr) == 0 {
-                                refresh_buffer!(self, size, in_ptr, in_end,
-                                                out, out_buf, buffer_begin);
-                            }
-                            *out = *in_ptr;
-                            out = out.offset(1);
-                            in_ptr = in_ptr.offset(1);
-                        } else {
-                            *out = 0;
-                            out = out.offset(1);
-                        }
-                    }
+/// What `verify` found out about a packed message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verification {
+    /// Number of bytes of the input occupied by the message.
+    pub packed_bytes: usize,
 
-                    if ptr_sub(in_end, in_ptr) == 0 && (tag == 0 || tag == 0xff) {
-                        refresh_buffer!(self, size, in_ptr, in_end,
-                                        out, out_buf, buffer_begin);
-                    }
-                } else {
-                    tag = *in_ptr;
-                    in_ptr = in_ptr.offset(1);
-
-                    for n in 0..8 {
-                        let is_nonzero = (tag & (1u8 << n)) != 0;
-                        *out = (*in_ptr) & ((-(is_nonzero as i8)) as u8);
-                        out = out.offset(1);
-                        in_ptr = in_ptr.offset(is_nonzero as isize);
-                    }
-                }
-                if tag == 0 {
-                    assert!(ptr_sub(in_end, in_ptr) > 0,
-                            "Should always have non-empty buffer here");
+    /// Number of segments in the message.
+    pub segment_count: usize,
 
-                    let run_length : usize = (*in_ptr) as usize * 8;
-                    in_ptr = in_ptr.offset(1);
+    /// Total number of words in the segments, not counting the segment table.
+    pub segment_words: usize,
 
-                    if run_length > ptr_sub(out_end, out) {
-                        return Err(io::Error::new(io::ErrorKind::Other,
-                                                  "Packed input did not end cleanly on a segment boundary"));
-                    }
+    /// Offset of the first byte at which the input differs from the result of packing the
+    /// unpacked message again, or `None` if the two are identical. The packed encoding is not
+    /// unique, so a difference does not by itself mean the input is invalid; other encoders may
+    /// choose runs differently.
+    pub first_mismatch: Option<usize>,
+}
 
-                    ptr::write_bytes(out, 0, run_length);
-                    out = out.offset(run_length as isize);
+/// Unpacks the message at the front of `packed`, validating its segment table, and then packs
+/// it again to check whether it round-trips byte for byte. Returns an error if the message
+/// cannot be unpacked at all.
+pub fn verify(packed: &[u8], options: ReaderOptions) -> Result<Verification> {
+    let mut input = packed;
+    let (table, segment_slices, owned_space) = try!(unpack_message_from_slice(&mut input, options));
+    let packed = &packed[..packed.len() - input.len()];
+
+    //# Pack the table and each segment with a separate write, as `write_message` does.
+    let mut packed_write = PackedWrite::new(Vec::with_capacity(packed.len()));
+    try!(packed_write.write_all(Word::words_to_bytes(&table[..])));
+    for &(a, b) in &segment_slices {
+        try!(packed_write.write_all(Word::words_to_bytes(&owned_space[a..b])));
+    }
+    let repacked = packed_write.into_inner();
+
+    let first_mismatch = match packed.iter().zip(repacked.iter()).position(|(a, b)| a != b) {
+        Some(idx) => Some(idx),
+        None if packed.len() != repacked.len() => Some(::std::cmp::min(packed.len(), repacked.len())),
+        None => None,
+    };
+
+    Ok(Verification {
+        packed_bytes: packed.len(),
+        segment_count: segment_slices.len(),
+        segment_words: owned_space.len(),
+        first_mismatch: first_mismatch,
+    })
+}
 
-                } else if tag == 0xff {
-                    assert!(ptr_sub(in_end, in_ptr) > 0,
-                            "Should always have non-empty buffer here");
+/// Reads a packed message that occupies all of `packed`. Returns an error if there are bytes
+/// left over after the message.
+pub fn unpack_from_slice(packed: &[u8],
+                         options: ReaderOptions)
+                         -> Result<::message::Reader<serialize::OwnedSegments>>
+{
+    let (message, consumed) = try!(read_message_from_slice(packed, options));
+    if consumed != packed.len() {
+        return Err(Error::new_decode_error("Trailing bytes after packed message.",
+                                           Some(format!("{}", packed.len() - consumed))));
+    }
+    Ok(message)
+}
 
-                    let mut run_length : usize = (*in_ptr) as usize * 8;
-                    in_ptr = in_ptr.offset(1);
+/// Iterator over concatenated packed messages in a stream. See `message_iter`.
+pub struct MessageIter<R> where R: BufRead {
+    read: R,
+    options: ReaderOptions,
+    failed: bool,
+}
 
-                    if run_length > ptr_sub(out_end, out) {
-                        return Err(io::Error::new(io::ErrorKind::Other,
-                                                  "Packed input did not end cleanly on a segment boundary"));
-                    }
+impl <R> MessageIter<R> where R: BufRead {
+    /// Returns the underlying stream, positioned just after the last message read.
+    pub fn into_inner(self) -> R {
+        self.read
+    }
+}
 
-                    let in_remaining = ptr_sub(in_end, in_ptr);
-                    if in_remaining >= run_length {
-                        //# Fast path.
-                        ptr::copy_nonoverlapping(in_ptr, out, run_length);
-                        out = out.offset(run_length as isize);
-                        in_ptr = in_ptr.offset(run_length as isize);
-                    } else {
-                        //# Copy over the first buffer, then do one big read for the rest.
-                        ptr::copy_nonoverlapping(in_ptr, out, in_remaining);
-                        out = out.offset(in_remaining as isize);
-                        run_length -= in_remaining;
-
-                        self.inner.consume(size);
-                        {
-                            let buf = slice::from_raw_parts_mut::<u8>(out, run_length);
-                            try!(read_exact(&mut self.inner, buf));
-                        }
-
-                        out = out.offset(run_length as isize);
-
-                        if out == out_end {
-                            return Ok(len);
-                        } else {
-                            let (b, e) = try!(self.get_read_buffer());
-                            in_ptr = b;
-                            in_end = e;
-                            size = ptr_sub(e, b);
-                            buffer_begin = in_ptr;
-                            continue;
-                        }
-                    }
-                }
+impl <R> Iterator for MessageIter<R> where R: BufRead {
+    type Item = Result<::message::Reader<serialize::OwnedSegments>>;
 
-                if out == out_end {
-                    self.inner.consume(ptr_sub(in_ptr, buffer_begin));
-                    return Ok(len);
-                }
+    fn next(&mut self) -> Option<Result<::message::Reader<serialize::OwnedSegments>>> {
+        if self.failed {
+            return None;
+        }
+        let at_end = match self.read.fill_buf() {
+            Ok(buf) => buf.is_empty(),
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(Error::from(e)));
             }
+        };
+        if at_end {
+            return None;
         }
+        let result = read_message(&mut self.read, self.options);
+        self.failed = result.is_err();
+        Some(result)
     }
 }
 
-/// Reads a packed message from a stream using the provided options.
-pub fn read_message<R>(read: &mut R,
-                       options: ReaderOptions)
-                       -> Result<::message::Reader<serialize::OwnedSegments>>
-    where R: BufRead
-{
-    let mut packed_read = PackedRead { inner: read };
-    serialize::read_message(&mut packed_read, options)
+/// Returns an iterator over the packed messages in `read`, which holds zero or more packed
+/// messages back to back. The unpacker consumes exactly the bytes of each message, so
+/// iteration picks up at the right place in the stream. Iteration ends cleanly when the
+/// stream ends between messages, and stops after the first error.
+pub fn message_iter<R>(read: R, options: ReaderOptions) -> MessageIter<R> where R: BufRead {
+    MessageIter { read: read, options: options, failed: false }
 }
 
-struct PackedWrite<W> where W: Write {
+/// A `Write` adaptor that packs everything written to it before passing it on to `inner`.
+///
+/// Packing operates on whole words, so every buffer passed to `write` must have a length that
+/// is a multiple of eight; other lengths are rejected with an `InvalidInput` error. Any
+/// sequence of word-aligned writes can be unpacked again with `PackedRead`, so one
+/// `PackedWrite` can be reused for many messages, or for data that was serialized elsewhere.
+pub struct PackedWrite<W> where W: Write {
     inner: W,
 }
 
+impl <W> PackedWrite<W> where W: Write {
+    pub fn new(inner: W) -> PackedWrite<W> {
+        PackedWrite { inner: inner }
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Returns a byte whose bit `i` is set if and only if byte `i` of the word at `ptr` is nonzero.
+/// This is the tag byte that precedes the word in the packed encoding.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+unsafe fn nonzero_tag(ptr: *const u8) -> u8 {
+    use std::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadl_epi64, _mm_movemask_epi8,
+                            _mm_setzero_si128};
+
+    //# SSE2 is always available on x86_64.
+    let word = _mm_loadl_epi64(ptr as *const __m128i);
+    let zeros = _mm_movemask_epi8(_mm_cmpeq_epi8(word, _mm_setzero_si128()));
+    !(zeros as u8)
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[inline]
+unsafe fn nonzero_tag(ptr: *const u8) -> u8 {
+    nonzero_tag_portable(ptr)
+}
+
+/// Portable version of `nonzero_tag`, which works a word at a time using integer arithmetic.
+#[cfg_attr(target_arch = "x86_64", allow(dead_code))]
+#[inline]
+unsafe fn nonzero_tag_portable(ptr: *const u8) -> u8 {
+    const LOW_SEVEN_BITS: u64 = 0x7f7f7f7f7f7f7f7f;
+    let word = u64::from_le(ptr::read_unaligned(ptr as *const u64));
+
+    //# Set the high bit of each byte that is nonzero, then gather the high bits into the
+    //# top byte with a multiply. Every partial product lands on a distinct bit, so there
+    //# are no carries.
+    let high_bits = ((((word & LOW_SEVEN_BITS) + LOW_SEVEN_BITS) | word) >> 7) & 0x0101010101010101;
+    (high_bits.wrapping_mul(0x0102040810204080) >> 56) as u8
+}
+
 impl <W> Write for PackedWrite<W> where W: Write {
     fn write(&mut self, in_buf: &[u8]) -> io::Result<usize> {
+        if in_buf.len() % 8 != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "PackedWrite writes must be word-aligned"));
+        }
+
         unsafe {
             let mut buf_idx: usize = 0;
             let mut buf: [u8; 64] = [0; 64];
 
-            let mut in_ptr: *const u8 = in_buf.get_unchecked(0);
-            let in_end: *const u8 = in_buf.get_unchecked(in_buf.len());
+            let mut in_ptr: *const u8 = in_buf.as_ptr();
+            let in_end: *const u8 = in_ptr.offset(in_buf.len() as isize);
 
             while in_ptr < in_end {
 
@@ -240,72 +498,41 @@ impl <W> Write for PackedWrite<W> where W: Write {
                     buf_idx = 0;
                 }
 
-                let tag_pos = buf_idx;
+                //# Classify the whole word at once, then copy out only the nonzero bytes.
+                let tag = nonzero_tag(in_ptr);
+                *buf.get_unchecked_mut(buf_idx) = tag;
                 buf_idx += 1;
 
-                let bit0 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit0 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit1 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit1 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit2 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit2 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit3 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit3 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit4 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit4 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit5 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit5 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit6 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit6 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let bit7 = (*in_ptr != 0) as u8;
-                *buf.get_unchecked_mut(buf_idx) = *in_ptr;
-                buf_idx += bit7 as usize;
-                in_ptr = in_ptr.offset(1);
-
-                let tag: u8 = (bit0 << 0) | (bit1 << 1) | (bit2 << 2) | (bit3 << 3)
-                            | (bit4 << 4) | (bit5 << 5) | (bit6 << 6) | (bit7 << 7);
-
-
-                *buf.get_unchecked_mut(tag_pos) = tag;
+                if tag == 0xff {
+                    ptr::copy_nonoverlapping(in_ptr, buf.as_mut_ptr().offset(buf_idx as isize), 8);
+                    buf_idx += 8;
+                } else {
+                    let mut remaining = tag;
+                    while remaining != 0 {
+                        let idx = remaining.trailing_zeros() as isize;
+                        *buf.get_unchecked_mut(buf_idx) = *in_ptr.offset(idx);
+                        buf_idx += 1;
+                        remaining &= remaining - 1;
+                    }
+                }
+                in_ptr = in_ptr.offset(8);
 
                 if tag == 0 {
                     //# An all-zero word is followed by a count of
                     //# consecutive zero words (not including the first
                     //# one).
 
-                    let mut in_word : *const u64 = in_ptr as *const u64;
-                    let mut limit : *const u64 = in_end as *const u64;
-                    if ptr_sub(limit, in_word) > 255 {
-                        limit = in_word.offset(255);
+                    let run_start = in_ptr;
+                    let mut limit = in_end;
+                    if ptr_sub(limit, in_ptr) > 255 * 8 {
+                        limit = in_ptr.offset(255 * 8);
                     }
-                    while in_word < limit && *in_word == 0 {
-                        in_word = in_word.offset(1);
+                    while in_ptr < limit && nonzero_tag(in_ptr) == 0 {
+                        in_ptr = in_ptr.offset(8);
                     }
 
-                    *buf.get_unchecked_mut(buf_idx) = ptr_sub(in_word, in_ptr as *const u64) as u8;
+                    *buf.get_unchecked_mut(buf_idx) = (ptr_sub(in_ptr, run_start) / 8) as u8;
                     buf_idx += 1;
-                    in_ptr = in_word as *const u8;
                 } else if tag == 0xff {
                     //# An all-nonzero word is followed by a count of
                     //# consecutive uncompressed words, followed by the
@@ -321,20 +548,8 @@ impl <W> Write for PackedWrite<W> where W: Write {
                         limit = in_ptr.offset(255 * 8);
                     }
 
-                    while in_ptr < limit {
-                        let mut c = 0;
-
-                        for _ in 0..8 {
-                            c += (*in_ptr == 0) as u8;
-                            in_ptr = in_ptr.offset(1);
-                        }
-
-                        if c >= 2 {
-                            //# Un-read the word with multiple zeros, since
-                            //# we'll want to compress that one.
-                            in_ptr = in_ptr.offset(-8);
-                            break;
-                        }
+                    while in_ptr < limit && nonzero_tag(in_ptr).count_zeros() < 2 {
+                        in_ptr = in_ptr.offset(8);
                     }
 
                     let count: usize = ptr_sub(in_ptr, run_start);
@@ -363,11 +578,44 @@ pub fn write_message<W, A>(write: &mut W, message : &::message::Builder<A>) -> i
     serialize::write_message(&mut packed_write, message)
 }
 
+/// A writer that discards its input, keeping count of the number of bytes written.
+struct CountingWrite {
+    count: usize,
+}
+
+impl Write for CountingWrite {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.count += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Computes the exact number of bytes that `write_message` would write for `message`, by
+/// running the packer without keeping its output.
+pub fn compute_packed_size<A>(message : &::message::Builder<A>) -> usize
+    where A: ::message::Allocator
+{
+    let mut counter = CountingWrite { count: 0 };
+    write_message(&mut counter, message).expect("CountingWrite never fails");
+    counter.count
+}
+
+/// Packs `message` into a new vector.
+pub fn pack_to_vec<A>(message : &::message::Builder<A>) -> Vec<u8>
+    where A: ::message::Allocator
+{
+    let mut packed = Vec::new();
+    write_message(&mut packed, message).expect("writing to a Vec never fails");
+    packed
+}
+
 #[cfg(test)]
 mod tests {
 
     use std::iter;
-    use std::io::Write;
+    use std::io::{self, BufReader, Read, Write};
 
     use std::io::Cursor;
     use quickcheck::{quickcheck, TestResult};
@@ -376,7 +624,7 @@ mod tests {
     use message::{ReaderOptions, ReaderSegments};
     use serialize::test::write_message_segments;
     use serialize_packed::{PackedRead, PackedWrite};
-    use super::read_message;
+    use super::{nonzero_tag, nonzero_tag_portable, read_message, read_message_from_slice};
     use util::read_exact;
 
     pub fn expect_packs_to(unpacked : &[u8],
@@ -396,7 +644,7 @@ mod tests {
         // --------
         // read
 
-        let mut packed_read = PackedRead {inner: packed};
+        let mut packed_read = PackedRead::new(packed);
 
 
         let mut bytes : Vec<u8> = iter::repeat(0u8).take(unpacked.len()).collect();
@@ -404,6 +652,22 @@ mod tests {
 
         //    assert!(packed_read.eof());
         assert_eq!(bytes, unpacked);
+
+        // --------
+        // read in small, unaligned pieces
+
+        let mut bytes = Vec::new();
+        PackedRead::new(BufReader::with_capacity(3, packed)).read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, unpacked);
+
+        // --------
+        // read truncated input
+
+        if packed.len() > 0 {
+            let mut bytes = Vec::new();
+            let result = PackedRead::new(&packed[..packed.len() - 1]).read_to_end(&mut bytes);
+            assert_eq!(io::ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+        }
     }
 
     #[test]
@@ -433,6 +697,234 @@ mod tests {
         expect_packs_to(&[0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0, 0,0,0,0,0,0,0,0], &[0,2]);
     }
 
+    #[test]
+    fn check_nonzero_tag() {
+        fn check(word: Vec<u8>) -> TestResult {
+            if word.len() < 8 { return TestResult::discard(); }
+            let expected = (0..8).fold(0u8, |tag, i| tag | (((word[i] != 0) as u8) << i));
+            unsafe {
+                TestResult::from_bool(expected == nonzero_tag(word.as_ptr()) &&
+                                      expected == nonzero_tag_portable(word.as_ptr()))
+            }
+        }
+
+        quickcheck(check as fn(Vec<u8>) -> TestResult);
+        for i in 0..8 {
+            let mut word = [0u8; 8];
+            word[i] = 0x80;
+            unsafe {
+                assert_eq!(1 << i, nonzero_tag(word.as_ptr()));
+                assert_eq!(1 << i, nonzero_tag_portable(word.as_ptr()));
+            }
+        }
+    }
+
+    #[test]
+    fn check_read_message_from_slice() {
+        let first = vec![vec![Word::from(0); 3], vec![Word::from(0x0102030405060708); 2]];
+        let second = vec![vec![Word::from(7); 1]];
+        let mut packed = Vec::new();
+        write_message_segments(&mut PackedWrite { inner: &mut packed }, &first);
+        let first_len = packed.len();
+        write_message_segments(&mut PackedWrite { inner: &mut packed }, &second);
+
+        let (message, consumed) = read_message_from_slice(&packed[..], ReaderOptions::new()).unwrap();
+        assert_eq!(first_len, consumed);
+        let result_segments = message.into_segments();
+        for (i, segment) in first.iter().enumerate() {
+            assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+        }
+
+        let (message, consumed) = read_message_from_slice(&packed[first_len..], ReaderOptions::new()).unwrap();
+        assert_eq!(packed.len() - first_len, consumed);
+        assert_eq!(&second[0][..], message.into_segments().get_segment(0).unwrap());
+
+        assert!(read_message_from_slice(&packed[..first_len - 1], ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn check_compute_packed_size() {
+        use message;
+        use super::{compute_packed_size, write_message};
+
+        let mut message = message::Builder::new(
+            message::HeapAllocator::new().first_segment_words(1));
+        message.set_root("this text does not fit in the first segment").unwrap();
+        let mut packed = Vec::new();
+        write_message(&mut packed, &message).unwrap();
+        assert_eq!(packed.len(), compute_packed_size(&message));
+    }
+
+    #[test]
+    fn check_pack_to_vec() {
+        use message;
+        use super::{pack_to_vec, unpack_from_slice};
+
+        let mut message = message::Builder::new_default();
+        message.set_root("packed").unwrap();
+        let mut packed = pack_to_vec(&message);
+        {
+            let reader = unpack_from_slice(&packed[..], ReaderOptions::new()).unwrap();
+            assert_eq!("packed", reader.get_root::<&str>().unwrap());
+        }
+
+        packed.push(0);
+        assert!(unpack_from_slice(&packed[..], ReaderOptions::new()).is_err());
+    }
+
+    #[test]
+    fn check_message_iter() {
+        use std::io::BufReader;
+        use super::message_iter;
+
+        let all_segments = vec![vec![vec![Word::from(0); 3], vec![Word::from(0x0102030405060708); 2]],
+                                vec![vec![Word::from(7); 1]],
+                                vec![vec![Word::from(0); 300]]];
+        let mut packed = Vec::new();
+        for segments in &all_segments {
+            write_message_segments(&mut PackedWrite { inner: &mut packed }, segments);
+        }
+
+        let mut count = 0;
+        for (message, segments) in message_iter(BufReader::with_capacity(5, &packed[..]),
+                                                ReaderOptions::new()).zip(all_segments.iter()) {
+            let result_segments = message.unwrap().into_segments();
+            for (i, segment) in segments.iter().enumerate() {
+                assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+            }
+            count += 1;
+        }
+        assert_eq!(3, count);
+        assert_eq!(3, message_iter(&packed[..], ReaderOptions::new()).count());
+    }
+
+    #[test]
+    fn check_message_iter_truncated() {
+        use super::message_iter;
+
+        let all_segments = vec![vec![vec![Word::from(0); 3], vec![Word::from(0x0102030405060708); 2]],
+                                vec![vec![Word::from(7); 1]]];
+        let mut packed = Vec::new();
+        let mut boundaries = vec![0];
+        for segments in &all_segments {
+            write_message_segments(&mut PackedWrite { inner: &mut packed }, segments);
+            boundaries.push(packed.len());
+        }
+
+        for cut in 0..packed.len() {
+            let results: Vec<_> = message_iter(BufReader::with_capacity(5, &packed[..cut]),
+                                               ReaderOptions::new()).collect();
+            let complete = boundaries.iter().filter(|&&b| b > 0 && b <= cut).count();
+            if boundaries.contains(&cut) {
+                assert_eq!(complete, results.len());
+            } else {
+                assert_eq!(complete + 1, results.len());
+                assert!(results[complete].is_err());
+            }
+            assert!(results[..complete].iter().all(|result| result.is_ok()));
+        }
+    }
+
+    #[test]
+    fn check_decompressed_size_limit() {
+        // A table claiming a single 2^20-word segment, followed by one zero run covering 256
+        // words of it. The input is tiny, but the message would unpack to 8 MiB.
+        let packed = [0x40, 0x10, 0x00, 0xff];
+        let mut options = ReaderOptions::new();
+        options.traversal_limit_in_words(1 << 16);
+        fn is_too_large<T>(result: ::Result<T>) -> bool {
+            match result {
+                Err(::Error::Decode { description, .. }) => description.starts_with("Message is too large"),
+                _ => false,
+            }
+        }
+        assert!(is_too_large(read_message(&mut &packed[..], options)));
+        assert!(is_too_large(read_message_from_slice(&packed[..], options)));
+    }
+
+    #[test]
+    fn check_packed_read_limit() {
+        // A zero word followed by a run of 255 more describes 2 KiB of output.
+        let packed = [0x00, 0xff];
+        let mut bytes = Vec::new();
+        let result = PackedRead::with_limit(&packed[..], 2047).read_to_end(&mut bytes);
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+        assert!(bytes.is_empty());
+
+        let mut bytes = Vec::new();
+        PackedRead::with_limit(&packed[..], 2048).read_to_end(&mut bytes).unwrap();
+        assert_eq!(2048, bytes.len());
+    }
+
+    #[test]
+    fn check_packed_write_reuse() {
+        use message;
+        use serialize;
+
+        let mut message = message::Builder::new_default();
+        message.set_root("reused").unwrap();
+        let mut packed_write = PackedWrite::new(Vec::new());
+        serialize::write_message(&mut packed_write, &message).unwrap();
+        serialize::write_message(&mut packed_write, &message).unwrap();
+        assert!(packed_write.write(&[1, 2, 3]).is_err());
+
+        let packed = packed_write.into_inner();
+        let mut read = &packed[..];
+        for _ in 0..2 {
+            let reader = read_message(&mut read, ReaderOptions::new()).unwrap();
+            assert_eq!("reused", reader.get_root::<&str>().unwrap());
+        }
+        assert!(read.is_empty());
+    }
+
+    #[test]
+    fn check_packed_read_adaptor() {
+        use serialize;
+
+        let segments = vec![vec![Word::from(0); 3], vec![Word::from(0x0102030405060708); 2]];
+        let mut packed = Vec::new();
+        write_message_segments(&mut PackedWrite::new(&mut packed), &segments);
+        packed.push(0xaa);
+
+        let mut packed_read = PackedRead::new(&packed[..]);
+        let message = serialize::read_message(&mut packed_read, ReaderOptions::new()).unwrap();
+        let result_segments = message.into_segments();
+        for (i, segment) in segments.iter().enumerate() {
+            assert_eq!(&segment[..], result_segments.get_segment(i as u32).unwrap());
+        }
+        assert_eq!(&[0xaa], packed_read.into_inner());
+
+        // 0xaa is the tag of a word with four nonzero bytes, which are missing.
+        let result = PackedRead::new(&[0xaa][..]).read(&mut [0; 3]);
+        assert_eq!(io::ErrorKind::UnexpectedEof, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn check_verify() {
+        use super::verify;
+
+        let segments = vec![vec![Word::from(0); 3], vec![Word::from(0x0102030405060708); 2]];
+        let mut packed = Vec::new();
+        write_message_segments(&mut PackedWrite::new(&mut packed), &segments);
+        let verification = verify(&packed[..], ReaderOptions::new()).unwrap();
+        assert_eq!(packed.len(), verification.packed_bytes);
+        assert_eq!(2, verification.segment_count);
+        assert_eq!(5, verification.segment_words);
+        assert_eq!(None, verification.first_mismatch);
+
+        // A zero word written as a tag with no run, then a run of the remaining two, rather
+        // than a single run of three, unpacks the same but does not round-trip.
+        assert_eq!(&[0, 2], &packed[5..7]);
+        let mut variant = packed[..5].to_vec();
+        variant.extend([0, 0, 0, 1].iter().cloned());
+        variant.extend(packed[7..].iter().cloned());
+        let verification = verify(&variant[..], ReaderOptions::new()).unwrap();
+        assert_eq!(variant.len(), verification.packed_bytes);
+        assert_eq!(Some(6), verification.first_mismatch);
+
+        assert!(verify(&packed[..packed.len() - 1], ReaderOptions::new()).is_err());
+    }
+
     #[test]
     fn check_round_trip() {
         fn round_trip(segments: Vec<Vec<Word>>) -> TestResult {
@@ -453,3 +945,73 @@ mod tests {
         quickcheck(round_trip as fn(Vec<Vec<Word>>) -> TestResult);
     }
 }
+
+/// Benchmarks for the tag computation in `PackedWrite`, comparing the SSE2 and portable versions
+/// of `nonzero_tag` with the byte-at-a-time loop that they replaced. Run with
+/// `cargo bench --features unstable` on a nightly compiler.
+#[cfg(all(test, feature = "unstable"))]
+mod bench {
+    use std::io::Write;
+    use test::{black_box, Bencher};
+
+    use super::{nonzero_tag_portable, PackedWrite};
+
+    /// 64 KiB of words in which roughly half of the bytes are zero, in no particular pattern.
+    fn sample_bytes() -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..64 * 1024).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            if (state >> 16) & 1 == 0 { 0 } else { (state >> 24) as u8 }
+        }).collect()
+    }
+
+    /// The tag computation that `PackedWrite` used before `nonzero_tag`: one comparison per byte.
+    #[inline]
+    unsafe fn nonzero_tag_scalar(ptr: *const u8) -> u8 {
+        let mut tag = 0;
+        for i in 0..8 {
+            tag |= ((*ptr.offset(i) != 0) as u8) << i;
+        }
+        tag
+    }
+
+    fn bench_tag<F>(b: &mut Bencher, tag: F) where F: Fn(*const u8) -> u8 {
+        let bytes = sample_bytes();
+        b.bytes = bytes.len() as u64;
+        b.iter(|| {
+            let mut sum = 0u32;
+            for word in bytes.chunks(8) {
+                sum = sum.wrapping_add(tag(word.as_ptr()) as u32);
+            }
+            black_box(sum)
+        });
+    }
+
+    #[bench]
+    fn tag_scalar(b: &mut Bencher) {
+        bench_tag(b, |ptr| unsafe { nonzero_tag_scalar(ptr) });
+    }
+
+    #[bench]
+    fn tag_portable(b: &mut Bencher) {
+        bench_tag(b, |ptr| unsafe { nonzero_tag_portable(ptr) });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[bench]
+    fn tag_sse2(b: &mut Bencher) {
+        bench_tag(b, |ptr| unsafe { super::nonzero_tag(ptr) });
+    }
+
+    #[bench]
+    fn pack(b: &mut Bencher) {
+        let bytes = sample_bytes();
+        let mut out = Vec::with_capacity(bytes.len() * 2);
+        b.bytes = bytes.len() as u64;
+        b.iter(|| {
+            out.clear();
+            PackedWrite { inner: &mut out }.write_all(&bytes[..]).unwrap();
+            black_box(out.len())
+        });
+    }
+}
@@ -23,7 +23,7 @@
 
 use private::layout::{ListReader, ListBuilder, PointerReader, PointerBuilder, InlineComposite};
 use traits::{FromPointerReader, FromPointerBuilder,
-             FromStructBuilder, FromStructReader, HasStructSize,
+             FromStructBuilder, FromStructReader, HasStructSize, IntoInternalStructReader,
              IndexMove, ListIter};
 use Result;
 
@@ -81,6 +81,15 @@ where T: for<'b> ::traits::OwnedStruct<'b> {
     }
 }
 
+impl <'a, T> ::std::iter::IntoIterator for Reader<'a, T>
+where T: for<'b> ::traits::OwnedStruct<'b> {
+    type Item = <T as ::traits::OwnedStruct<'a>>::Reader;
+    type IntoIter = ListIter<Reader<'a, T>, <T as ::traits::OwnedStruct<'a>>::Reader>;
+    fn into_iter(self) -> ListIter<Reader<'a, T>, <T as ::traits::OwnedStruct<'a>>::Reader> {
+        self.iter()
+    }
+}
+
 impl <'a, T> Reader<'a, T> where T: for<'b> ::traits::OwnedStruct<'b> {
     pub fn get(self, index: u32) -> <T as ::traits::OwnedStruct<'a>>::Reader {
         assert!(index < self.len());
@@ -135,6 +144,31 @@ impl <'a, T> Builder<'a, T> where T: for<'b> ::traits::OwnedStruct<'b> {
         assert!(index < self.len());
         FromStructBuilder::new(self.builder.get_struct_element(index))
     }
+
+    /// Deep-copies `value` into the element at `index`. The element keeps the struct size of
+    /// this list, so fields that `value` does not have are zeroed and fields that the list's
+    /// elements do not have are dropped.
+    pub fn set_with_caveats<'b>(&mut self, index: u32, value: <T as ::traits::OwnedStruct<'b>>::Reader)
+                                -> Result<()>
+        where <T as ::traits::OwnedStruct<'b>>::Reader: IntoInternalStructReader<'b>
+    {
+        assert!(index < self.len());
+        self.builder.get_struct_element(index).copy_content_from(&value.into_internal_struct_reader())
+    }
+
+    /// Iterates over the elements. Each element is a distinct part of the message, so the
+    /// yielded builders can all be held at once.
+    pub fn iter(self) -> ListIter<Builder<'a, T>, <T as ::traits::OwnedStruct<'a>>::Builder> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
+}
+
+impl <'a, T>  IndexMove<u32, <T as ::traits::OwnedStruct<'a>>::Builder> for Builder<'a, T>
+where T: for<'b> ::traits::OwnedStruct<'b> {
+    fn index_move(&self, index : u32) -> <T as ::traits::OwnedStruct<'a>>::Builder {
+        Builder::<'a, T> { builder : self.builder, marker : ::std::marker::PhantomData }.get(index)
+    }
 }
 
 impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
@@ -145,3 +179,55 @@ impl <'a, T> ::traits::SetPointerBuilder<Builder<'a, T>> for Reader<'a, T>
         pointer.set_list(&value.reader)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use private::layout::{StructReader, StructBuilder, StructSize};
+    use traits::{FromStructReader, FromStructBuilder, HasStructSize, IntoInternalStructReader};
+
+    // Stands in for a generated struct with a single UInt64 field.
+    struct Point;
+
+    impl <'a> ::traits::OwnedStruct<'a> for Point {
+        type Reader = PointReader<'a>;
+        type Builder = PointBuilder<'a>;
+    }
+
+    struct PointReader<'a> { reader : StructReader<'a> }
+
+    impl <'a> FromStructReader<'a> for PointReader<'a> {
+        fn new(reader : StructReader<'a>) -> PointReader<'a> { PointReader { reader : reader } }
+    }
+
+    impl <'a> IntoInternalStructReader<'a> for PointReader<'a> {
+        fn into_internal_struct_reader(self) -> StructReader<'a> { self.reader }
+    }
+
+    struct PointBuilder<'a> { builder : StructBuilder<'a> }
+
+    impl <'a> FromStructBuilder<'a> for PointBuilder<'a> {
+        fn new(builder : StructBuilder<'a>) -> PointBuilder<'a> { PointBuilder { builder : builder } }
+    }
+
+    impl <'a> HasStructSize for PointBuilder<'a> {
+        fn struct_size() -> StructSize { StructSize { data : 1, pointers : 0 } }
+    }
+
+    #[test]
+    fn set_with_caveats() {
+        use message::tests::build_message;
+
+        let source = build_message(|root| {
+            let list : super::Builder<Point> = root.initn_as(1);
+            list.get(0).builder.set_data_field::<u64>(0, 42);
+        });
+        let message = build_message(|root| {
+            let mut list : super::Builder<Point> = root.initn_as(2);
+            let value = source.get_root_as_reader::<super::Reader<Point>>().unwrap().get(0);
+            list.set_with_caveats(1, value).unwrap();
+        });
+        let list = message.get_root_as_reader::<super::Reader<Point>>().unwrap();
+        assert_eq!(0, list.get(0).reader.get_data_field::<u64>(0));
+        assert_eq!(42, list.get(1).reader.get_data_field::<u64>(0));
+    }
+}
@@ -21,6 +21,8 @@
 
 //! UTF-8 encoded text.
 
+use message::Utf8Policy;
+use private::layout::{Byte, ListRef};
 use {Error, Result};
 
 #[derive(Copy, Clone)]
@@ -41,6 +43,44 @@ pub fn new_reader<'a>(v : &'a [u8]) -> Result<Reader<'a>> {
     }
 }
 
+/// Like `new_reader()`, but handles invalid UTF-8 according to `policy`.
+pub fn new_reader_with_policy<'a>(v : &'a [u8], policy : Utf8Policy) -> Result<Reader<'a>> {
+    match policy {
+        Utf8Policy::Strict => new_reader(v),
+        Utf8Policy::ValidPrefix => match ::std::str::from_utf8(v) {
+            Ok(v) => Ok(v),
+            Err(e) => Ok(unsafe { ::std::str::from_utf8_unchecked(&v[..e.valid_up_to()]) }),
+        },
+    }
+}
+
+/// Like `new_reader()`, but replaces invalid UTF-8 sequences with U+FFFD instead of failing.
+pub fn new_reader_lossy<'a>(v : &'a [u8]) -> ::std::borrow::Cow<'a, str> {
+    String::from_utf8_lossy(v)
+}
+
+/// Converts text to UTF-16, e.g. for passing to Windows APIs.
+#[cfg(feature="utf16")]
+pub fn to_utf16(text : Reader) -> Vec<u16> {
+    text.encode_utf16().collect()
+}
+
+/// Computes the number of bytes that `wide` takes up once converted to UTF-8, i.e. the size to
+/// initialize a text field with before calling `Builder::push_utf16()`. Returns an error if
+/// `wide` contains an unpaired surrogate.
+#[cfg(feature="utf16")]
+pub fn utf16_len_in_utf8(wide : &[u16]) -> Result<u32> {
+    let mut len = 0;
+    for c in ::std::char::decode_utf16(wide.iter().cloned()) {
+        match c {
+            Ok(c) => len += c.len_utf8() as u32,
+            Err(e) => return Err(Error::new_decode_error(
+                "UTF-16 text contains an unpaired surrogate.", Some(format!("{:?}", e)))),
+        }
+    }
+    Ok(len)
+}
+
 impl <'a> ::traits::FromPointerReader<'a> for Reader<'a> {
     fn get_from_pointer(reader : &::private::layout::PointerReader<'a>) -> Result<Reader<'a>> {
         reader.get_text(::std::ptr::null(), 0)
@@ -50,33 +90,102 @@ impl <'a> ::traits::FromPointerReader<'a> for Reader<'a> {
 pub struct Builder<'a> {
     bytes : &'a mut [u8],
     pos : usize,
+    list_ref : Option<&'a mut ListRef>,
 }
 
 impl <'a> Builder <'a> {
 
     pub fn new<'b>(bytes : &'b mut [u8], pos : u32) -> Result<Builder<'b>> {
         if pos != 0 {
-            match ::std::str::from_utf8(bytes) {
+            match ::std::str::from_utf8(&bytes[..pos as usize]) {
                 Err(e) => return Err(Error::new_decode_error(
                     "Text contains non-utf8 data.", Some(format!("{:?}", e)))),
                 _ => {}
             }
         }
-        Ok(Builder { bytes : bytes, pos : pos as usize })
+        Ok(Builder { bytes : bytes, pos : pos as usize, list_ref : None })
+    }
+
+    #[doc(hidden)]
+    pub fn with_list_ref(self, list_ref : &'a mut ListRef) -> Builder<'a> {
+        Builder { bytes : self.bytes, pos : self.pos, list_ref : Some(list_ref) }
+    }
+
+    /// Gets the number of bytes that have been written. This can be less than `capacity()`.
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == 0
+    }
+
+    /// Gets the size of the text field in bytes, not counting the NUL terminator.
+    pub fn capacity(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Gets the number of bytes that can still be pushed. The size of a text field is fixed
+    /// when it is initialized.
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
     }
 
+    /// Appends an ASCII character.
+    ///
+    /// Panics if `ascii` is not ASCII or if the text is full.
     pub fn push_ascii(&mut self, ascii : u8) {
+        self.try_push_ascii(ascii).unwrap()
+    }
+
+    /// Appends an ASCII character, or returns an error if the text is full.
+    ///
+    /// Panics if `ascii` is not ASCII.
+    pub fn try_push_ascii(&mut self, ascii : u8) -> ::std::result::Result<(), CapacityError> {
         assert!(ascii < 128);
+        if self.remaining() < 1 {
+            return Err(CapacityError { needed: 1, remaining: self.remaining() });
+        }
         self.bytes[self.pos] = ascii;
         self.pos += 1;
+        Ok(())
+    }
+
+    /// Appends UTF-16 text, converting it to UTF-8. Returns an error, without writing anything,
+    /// if `wide` contains an unpaired surrogate or if the converted text does not fit in the
+    /// remaining space.
+    #[cfg(feature="utf16")]
+    pub fn push_utf16(&mut self, wide : &[u16]) -> Result<()> {
+        let len = try!(utf16_len_in_utf8(wide)) as usize;
+        if self.remaining() < len {
+            let e = CapacityError { needed: len, remaining: self.remaining() };
+            return Err(Error::new_decode_error("Text write does not fit in the remaining space.",
+                                               Some(format!("{}", e))));
+        }
+        for c in ::std::char::decode_utf16(wide.iter().cloned()) {
+            let mut buf = [0u8; 4];
+            self.push_str(c.unwrap().encode_utf8(&mut buf));
+        }
+        Ok(())
     }
 
+    /// Appends a string.
+    ///
+    /// Panics if the string does not fit in the remaining space.
     pub fn push_str(&mut self, string : &str) {
+        self.try_push_str(string).unwrap()
+    }
+
+    /// Appends a string, or returns an error without writing anything if it does not fit in
+    /// the remaining space.
+    pub fn try_push_str(&mut self, string : &str) -> ::std::result::Result<(), CapacityError> {
         let bytes = string.as_bytes();
-        for ii in 0..bytes.len() {
-            self.bytes[self.pos + ii] = bytes[ii];
+        if self.remaining() < bytes.len() {
+            return Err(CapacityError { needed: bytes.len(), remaining: self.remaining() });
         }
+        self.bytes[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
         self.pos += bytes.len();
+        Ok(())
     }
 
     pub fn clear(&mut self) {
@@ -85,18 +194,77 @@ impl <'a> Builder <'a> {
         }
         self.pos = 0;
     }
+
+    /// Shortens the text field to `new_len` bytes, zeroing the bytes that are cut off. When the
+    /// builder points into a message, the field's length in the message shrinks too, so a text
+    /// can be initialized larger than needed and cut down once its contents are known. The
+    /// space that is cut off is not reclaimed.
+    ///
+    /// Panics if `new_len` is greater than the size of the field or does not lie on a char
+    /// boundary.
+    pub fn truncate(&mut self, new_len : usize) {
+        assert!(new_len <= self.bytes.len(), "Cannot truncate text to a greater length.");
+        assert!(new_len >= self.pos || self.is_char_boundary(new_len),
+                "Cannot truncate text in the middle of a char.");
+        for ii in new_len..self.bytes.len() {
+            self.bytes[ii] = 0;
+        }
+        let bytes = ::std::mem::replace(&mut self.bytes, &mut []);
+        self.bytes = &mut bytes[..new_len];
+        if self.pos > new_len {
+            self.pos = new_len;
+        }
+        if let Some(ref mut list_ref) = self.list_ref {
+            // Include the NUL terminator.
+            list_ref.set(Byte, new_len as u32 + 1);
+        }
+    }
+}
+
+/// Allows formatting directly into a text field with `write!`. A formatted value that does not
+/// fit results in `fmt::Error`; pieces that were written before the one that did not fit are
+/// kept.
+impl <'a> ::std::fmt::Write for Builder<'a> {
+    fn write_str(&mut self, string : &str) -> ::std::fmt::Result {
+        self.try_push_str(string).map_err(|_| ::std::fmt::Error)
+    }
+}
+
+/// Error returned when a write does not fit in the remaining space of a text builder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CapacityError {
+    /// Number of bytes that the write needed.
+    pub needed : usize,
+
+    /// Number of bytes that were left in the text.
+    pub remaining : usize,
+}
+
+impl ::std::fmt::Display for CapacityError {
+    fn fmt(&self, fmt : &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        write!(fmt, "Text write of {} bytes does not fit in the remaining {} bytes.",
+               self.needed, self.remaining)
+    }
 }
 
+impl ::std::error::Error for CapacityError {
+    fn description(&self) -> &str {
+        "Text write does not fit in the remaining space."
+    }
+}
+
+/// Derefs to the text written so far. The bytes up to `pos` are valid UTF-8: they were either
+/// checked in `new()` or written from a `&str`, so they do not need to be checked again.
 impl <'a> ::std::ops::Deref for Builder <'a> {
     type Target = str;
     fn deref<'b>(&'b self) -> &'b str {
-        ::std::str::from_utf8(self.bytes).unwrap()
+        unsafe { ::std::str::from_utf8_unchecked(&self.bytes[..self.pos]) }
     }
 }
 
 impl <'a> ::std::convert::AsRef<str> for Builder<'a> {
     fn as_ref<'b>(&'b self) -> &'b str {
-        ::std::str::from_utf8(self.bytes).unwrap()
+        &**self
     }
 }
 
@@ -115,3 +283,100 @@ impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Builder, CapacityError};
+
+    #[test]
+    fn try_push() {
+        let mut bytes = [0u8; 5];
+        let mut text = Builder::new(&mut bytes, 0).unwrap();
+        text.try_push_str("abc").unwrap();
+        assert_eq!(5, text.capacity());
+        assert_eq!(Err(CapacityError { needed: 3, remaining: 2 }), text.try_push_str("def"));
+        text.try_push_ascii(b'd').unwrap();
+        text.push_ascii(b'e');
+        assert_eq!(Err(CapacityError { needed: 1, remaining: 0 }), text.try_push_ascii(b'f'));
+        assert_eq!("abcde", &*text);
+    }
+
+    #[test]
+    fn write_fmt() {
+        use std::fmt::Write;
+
+        let mut bytes = [0u8; 16];
+        let mut text = Builder::new(&mut bytes, 0).unwrap();
+        write!(text, "user {} at {}", 42, 7).unwrap();
+        assert_eq!("user 42 at 7", &*text);
+        assert!(write!(text, "{}", 12345).is_err());
+    }
+
+    #[test]
+    fn truncate() {
+        let mut bytes = [0u8; 8];
+        let mut text = Builder::new(&mut bytes, 0).unwrap();
+        assert!(text.is_empty());
+        text.push_str("h\u{e9}llo");
+        assert_eq!(6, text.len());
+        text.truncate(3);
+        assert_eq!(3, text.len());
+        assert_eq!("h\u{e9}", &*text);
+        text.truncate(0);
+        assert!(text.is_empty());
+        assert_eq!("", &*text);
+    }
+
+    #[test]
+    #[should_panic]
+    fn truncate_inside_char() {
+        let mut bytes = [0u8; 4];
+        let mut text = Builder::new(&mut bytes, 0).unwrap();
+        text.push_str("h\u{e9}");
+        text.truncate(2);
+    }
+
+    #[cfg(feature="utf16")]
+    #[test]
+    fn utf16() {
+        let wide: Vec<u16> = "h\u{e9}llo \u{1f600}".encode_utf16().collect();
+        let len = super::utf16_len_in_utf8(&wide).unwrap();
+        assert_eq!(11, len);
+
+        let mut bytes = vec![0u8; len as usize];
+        {
+            let mut text = Builder::new(&mut bytes, 0).unwrap();
+            text.push_utf16(&wide).unwrap();
+            assert_eq!(wide, super::to_utf16(&text));
+            assert!(text.push_utf16(&wide[..1]).is_err());
+        }
+        assert!(super::utf16_len_in_utf8(&[0xd800]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_str_overflow() {
+        let mut bytes = [0u8; 2];
+        let mut text = Builder::new(&mut bytes, 0).unwrap();
+        text.push_str("abc");
+    }
+
+    #[test]
+    fn truncate_in_message() {
+        use message::tests::build_message;
+
+        let mut message = build_message(|root| {
+            let mut text: Builder = root.initn_as(20);
+            text.push_str("short");
+            let len = text.len();
+            text.truncate(len);
+        });
+        assert_eq!("short", message.get_root_as_reader::<&str>().unwrap());
+        {
+            let mut text: Builder = message.get_root().unwrap();
+            assert_eq!(5, text.len());
+            text.truncate(2);
+        }
+        assert_eq!("sh", message.get_root_as_reader::<&str>().unwrap());
+    }
+}
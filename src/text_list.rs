@@ -21,7 +21,7 @@
 
 //! List of strings containing UTF-8 encoded text.
 
-use traits::{FromPointerReader, FromPointerBuilder};
+use traits::{FromPointerReader, FromPointerBuilder, IndexMove, ListIter};
 use private::layout::{ListBuilder, ListReader, Pointer, PointerBuilder, PointerReader};
 use Result;
 
@@ -57,6 +57,42 @@ impl <'a> Reader<'a> {
         assert!(index <  self.len());
         self.reader.get_pointer_element(index).get_text(::std::ptr::null(), 0)
     }
+
+    pub fn iter(self) -> ListIter<Reader<'a>, Result<::text::Reader<'a>>> {
+        ListIter::new(self, self.len())
+    }
+
+    /// Concatenates the elements, placing `separator` between each pair. Fails on the first
+    /// element that cannot be read.
+    pub fn join(self, separator : &str) -> Result<String> {
+        let mut result = String::new();
+        for (idx, text) in self.iter().enumerate() {
+            if idx > 0 {
+                result.push_str(separator);
+            }
+            result.push_str(try!(text));
+        }
+        Ok(result)
+    }
+
+    /// Reads all of the elements into a `Vec`. Fails on the first element that cannot be read.
+    pub fn collect_into_vec(self) -> Result<Vec<::text::Reader<'a>>> {
+        self.iter().collect()
+    }
+}
+
+impl <'a> IndexMove<u32, Result<::text::Reader<'a>>> for Reader<'a> {
+    fn index_move(&self, index : u32) -> Result<::text::Reader<'a>> {
+        self.get(index)
+    }
+}
+
+impl <'a> ::std::iter::IntoIterator for Reader<'a> {
+    type Item = Result<::text::Reader<'a>>;
+    type IntoIter = ListIter<Reader<'a>, Result<::text::Reader<'a>>>;
+    fn into_iter(self) -> ListIter<Reader<'a>, Result<::text::Reader<'a>>> {
+        self.iter()
+    }
 }
 
 pub struct Builder<'a> {
@@ -75,6 +111,16 @@ impl <'a> Builder<'a> {
         self.builder.get_pointer_element(index).set_text(value);
     }
 
+    /// Sets the first `values.len()` elements.
+    ///
+    /// Panics if `values` is longer than the list.
+    pub fn set_all(&mut self, values : &[::text::Reader]) {
+        assert!(values.len() <= self.len() as usize);
+        for (idx, value) in values.iter().enumerate() {
+            self.set(idx as u32, value);
+        }
+    }
+
     pub fn borrow<'b>(&'b mut self) -> Builder<'b> {
         Builder {builder : self.builder}
     }
@@ -95,14 +141,70 @@ impl <'a> FromPointerBuilder<'a> for Builder<'a> {
 }
 
 impl <'a> Builder<'a> {
+    pub fn iter(self) -> ListIter<Builder<'a>, Result<::text::Builder<'a>>> {
+        let len = self.len();
+        ListIter::new(self, len)
+    }
+
     pub fn get(self, index : u32) -> Result<::text::Builder<'a>> {
         self.builder.get_pointer_element(index).get_text(::std::ptr::null(), 0)
     }
 }
 
+impl <'a> IndexMove<u32, Result<::text::Builder<'a>>> for Builder<'a> {
+    fn index_move(&self, index : u32) -> Result<::text::Builder<'a>> {
+        Builder { builder : self.builder }.get(index)
+    }
+}
+
 impl <'a> ::traits::SetPointerBuilder<Builder<'a>> for Reader<'a> {
     fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>, value : Reader<'a>) -> Result<()> {
         pointer.set_list(&value.reader)
     }
 }
 
+/// Allows a whole text list to be set from a slice of strings, sized to fit.
+impl <'a, 'c> ::traits::SetPointerBuilder<Builder<'a>> for &'c [::text::Reader<'c>] {
+    fn set_pointer_builder<'b>(pointer : ::private::layout::PointerBuilder<'b>,
+                               value : &'c [::text::Reader<'c>]) -> Result<()> {
+        let mut builder = Builder { builder : pointer.init_list(Pointer, value.len() as u32) };
+        builder.set_all(value);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+
+    #[test]
+    fn join() {
+        let message = build_message(|root| {
+            let mut list: super::Builder = root.initn_as(3);
+            list.set(0, "a");
+            list.set(1, "bc");
+            list.set(2, "");
+        });
+        let list: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!("a, bc, ", list.join(", ").unwrap());
+        assert_eq!(vec!["a", "bc", ""], list.collect_into_vec().unwrap());
+        assert_eq!(2, list.iter().filter(|t| !t.as_ref().unwrap().is_empty()).count());
+    }
+
+    #[test]
+    fn from_slice() {
+        let message = build_message(|root| {
+            root.set_as::<super::Builder, _>(&["a", "bc"][..]).unwrap();
+        });
+        let list: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(vec!["a", "bc"], list.collect_into_vec().unwrap());
+
+        let message = build_message(|root| {
+            let mut list: super::Builder = root.initn_as(3);
+            list.set_all(&["x", "y"]);
+        });
+        let list: super::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(vec!["x", "y", ""], list.collect_into_vec().unwrap());
+    }
+}
@@ -26,6 +26,19 @@ pub trait FromStructReader<'a> {
     fn new(reader : StructReader<'a>) -> Self;
 }
 
+/// Gives access to the untyped struct underlying a generated struct reader, so that it can be
+/// copied generically, e.g. by `struct_list::Builder::set_with_caveats()`. Generated readers
+/// implement this by handing back the `StructReader` that they wrap.
+pub trait IntoInternalStructReader<'a> {
+    fn into_internal_struct_reader(self) -> StructReader<'a>;
+}
+
+impl <'a> IntoInternalStructReader<'a> for StructReader<'a> {
+    fn into_internal_struct_reader(self) -> StructReader<'a> {
+        self
+    }
+}
+
 pub trait HasStructSize {
     fn struct_size() -> StructSize;
 }
@@ -120,4 +133,111 @@ impl <U, T : IndexMove<u32, U>> ::std::iter::Iterator for ListIter<T, U> {
             return None;
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.size - self.index) as usize;
+        (remaining, Some(remaining))
+    }
+
+    fn nth(&mut self, n : usize) -> ::std::option::Option<U> {
+        let remaining = (self.size - self.index) as usize;
+        if n < remaining {
+            self.index += n as u32;
+            self.next()
+        } else {
+            self.index = self.size;
+            None
+        }
+    }
+}
+
+impl <U, T : IndexMove<u32, U>> ::std::iter::ExactSizeIterator for ListIter<T, U> {}
+
+impl <U, T : IndexMove<u32, U>> ::std::iter::DoubleEndedIterator for ListIter<T, U> {
+    fn next_back(&mut self) -> ::std::option::Option<U> {
+        if self.index < self.size {
+            self.size -= 1;
+            Some(self.list.index_move(self.size))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use message::tests::build_message;
+    use data_list;
+    use list_list;
+    use primitive_list;
+
+    #[test]
+    fn into_iter() {
+        let message = build_message(|root| {
+            let mut list: primitive_list::Builder<u16> = root.initn_as(3);
+            for ii in 0..3 {
+                list.set(ii, 10 * ii as u16);
+            }
+        });
+        let list: primitive_list::Reader<u16> = message.get_root_as_reader().unwrap();
+        let mut sum = 0;
+        for x in list {
+            sum += x;
+        }
+        assert_eq!(30, sum);
+        assert_eq!(vec![0, 10, 20], list.into_iter().collect::<Vec<u16>>());
+
+        let message = build_message(|root| {
+            let mut list: data_list::Builder = root.initn_as(2);
+            list.set(0, b"ab");
+            list.set(1, b"c");
+        });
+        let list: data_list::Reader = message.get_root_as_reader().unwrap();
+        let lens: ::Result<Vec<usize>> = list.into_iter().map(|d| d.map(|d| d.len())).collect();
+        assert_eq!(vec![2, 1], lens.unwrap());
+    }
+
+    #[test]
+    fn double_ended() {
+        let message = build_message(|root| {
+            let mut list: primitive_list::Builder<u32> = root.initn_as(5);
+            list.set_from_slice(&[0, 1, 2, 3, 4]);
+        });
+        let list: primitive_list::Reader<u32> = message.get_root_as_reader().unwrap();
+        assert_eq!(5, list.iter().len());
+        assert_eq!(vec![4, 3, 2, 1, 0], list.iter().rev().collect::<Vec<u32>>());
+        assert_eq!(vec![3, 4], list.iter().skip(3).collect::<Vec<u32>>());
+
+        let mut iter = list.iter();
+        assert_eq!(Some(2), iter.nth(2));
+        assert_eq!(Some(4), iter.next_back());
+        assert_eq!(1, iter.len());
+        assert_eq!(None, iter.nth(1));
+        assert_eq!(None, iter.next());
+    }
+
+    #[test]
+    fn builder_iter() {
+        let message = build_message(|root| {
+            let mut list: data_list::Builder = root.initn_as(2);
+            list.set(0, b"ab");
+            list.set(1, b"c");
+            for data in list.iter() {
+                data.unwrap()[0] += 1;
+            }
+        });
+        let list: data_list::Reader = message.get_root_as_reader().unwrap();
+        assert_eq!(&b"bb"[..], list.get(0).unwrap());
+        assert_eq!(&b"d"[..], list.get(1).unwrap());
+
+        build_message(|root| {
+            let mut lists: list_list::Builder<primitive_list::Owned<u8>> = root.initn_as(3);
+            for ii in 0..3 {
+                lists.borrow().init(ii, ii + 1);
+            }
+            let builders: Vec<primitive_list::Builder<u8>> =
+                lists.iter().map(|l| l.unwrap()).collect();
+            assert_eq!(vec![1, 2, 3], builders.iter().map(|l| l.len()).collect::<Vec<u32>>());
+        });
+    }
 }